@@ -0,0 +1,2 @@
+// The physics, without any rendering, so it builds and runs anywhere
+pub mod simulation;
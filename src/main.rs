@@ -1,128 +1,297 @@
-use glfw;
-use gl;
-use nalgebra as na;
 use rand::prelude::*;
 
+use ::natu::simulation;
+
+#[cfg(feature = "render")]
 mod shaderutils;
+#[cfg(feature = "render")]
 mod modelutils;
+#[cfg(feature = "render")]
 mod object;
+#[cfg(feature = "render")]
 mod camera;
+#[cfg(feature = "render")]
+mod overlay;
 
+#[cfg(feature = "render")]
 mod natu;
 
 
 
-fn setup(game: &mut natu::Natu) {
-    game.load_object("resources/suzanne.obj","monkey","resources/rock.png");
-    game.get("monkey").mass = 0.0;
-    game.get("monkey").scale = 0.500;
-    game.get("monkey").position.x = 010.0;
-    game.camera.position.z = -4.0;
-    
-    for i in 0..100 {
-        let key = i.to_string();
-        game.load_object("resources/suzanne.obj", key.as_str(), "resources/missing.png");
-        let obj = game.get(key.as_str());
-        obj.scale = 0.1;
-        let mut rng = rand::thread_rng();
-        let ran = 10.0;
-        obj.position.x = ran*(0.5-rng.gen::<f32>());
-        obj.position.y = ran*(0.5-rng.gen::<f32>());
-        obj.position.z = ran*(0.5-rng.gen::<f32>());
+fn setup(sim: &mut simulation::Simulation) {
+    sim.set_integrator(Box::new(simulation::integrator::Leapfrog::new()));
+    sim.world.gravity.set_units(simulation::units::UnitSystem::NBody);
+    sim.world.gravity.kernel = simulation::softening::Kernel::Plummer;
+    sim.world.gravity.softening_length = 0.05;
+    sim.diagnostics.interval = 60;
+    sim.world.collisions = Some(simulation::collision::Response::Merge);
 
+    // Fixed landmark
+    let mut monkey = simulation::Body::new("monkey");
+    monkey.mass = 0.0;
+    monkey.kind = simulation::Kind::Pinned(None);
+    monkey.radius = 0.5;
+    monkey.position.x = 10.0;
+    sim.world.add_body(monkey);
+
+    let mut sun = simulation::Body::new("sun");
+    sun.mass = 1.0;
+    sun.radius = 0.3;
+    sim.world.add_body(sun);
+
+    // Light bodies on random, mildly eccentric and inclined orbits about the sun
+    let mut rng = rand::thread_rng();
+    let tau = std::f64::consts::TAU as simulation::Real;
+    for i in 0..100 {
+        let mut body = simulation::Body::new(i.to_string().as_str());
+        body.mass = 1e-5;
+        body.radius = 0.1;
+        let elements = simulation::elements::Elements {
+            semi_major_axis: 1.0 + 4.0*rng.gen::<simulation::Real>(),
+            eccentricity: 0.2*rng.gen::<simulation::Real>(),
+            inclination: 0.1*rng.gen::<simulation::Real>(),
+            longitude_of_ascending_node: tau*rng.gen::<simulation::Real>(),
+            argument_of_periapsis: tau*rng.gen::<simulation::Real>(),
+            true_anomaly: tau*rng.gen::<simulation::Real>(),
+        };
+        sim.world.add_body_from_elements(body, "sun", &elements);
     }
-}
 
-fn tick(game: &mut natu::Natu) {
-    nbody(game);
-    apply_physics(game);
-    spectate(game, "0")
+    // Random orbits leave the system with some net momentum
+    sim.recentre();
 }
 
-// 
-fn spectate(game: &mut natu::Natu, object_name: &str) {
-    if game.get(object_name).velocity.magnitude() != 0.0 {
-        game.camera.position = game.get(object_name).position - game.camera.direction*(10.0 as f32);
-    }
+// Sum gravity with the solver called `name`
+fn select_solver(sim: &mut simulation::Simulation, name: &str) {
+    use simulation::gravity::*;
+
+    sim.world.solver = match name {
+        "direct" => Box::new(DirectSum),
+        "barnes-hut" => Box::new(barnes_hut::BarnesHut::new(0.5)),
+        "fmm" => Box::new(fmm::FastMultipole::new(4, 0.5)),
+        "pm" => {
+            // Periodic forces need a periodic box. Centre it on the bodies, which start around
+            // the origin.
+            let size = 32.0;
+            sim.world.periodic_box = Some(size);
+            let bodies = &mut sim.world.bodies;
+            for i in 0..bodies.len() {
+                bodies.set_position(i, bodies.position(i) + simulation::Vector::repeat(0.5*size));
+            }
+            Box::new(particle_mesh::ParticleMesh::new(64, size))
+        }
+        _ => panic!("unknown solver \"{}\", expected direct, barnes-hut, fmm or pm", name),
+    };
 }
 
-fn nbody(game: &mut natu::Natu) {
-    // (Key, Pos, Mass) for each object
+// Add the force called `name` on top of gravity, acting on the bodies of `setup`
+fn add_force(sim: &mut simulation::Simulation, name: &str) {
+    use simulation::force::*;
 
-    let G = 10.0;
+    let sun = sim.world.find("sun").expect("forces act around the sun");
+    let force: Box<dyn Force> = match name {
+        // Gas the bodies spiral in through
+        "drag" => Box::new(drag::Drag::linear(0.01)),
+        "radiation" => {
+            let bodies = &mut sim.world.bodies;
+            for i in (0..bodies.len()).filter(|i| bodies.names[*i] != sun.name) {
+                bodies.radiation_pressure[i] = 0.1;
+            }
+            Box::new(radiation_pressure::RadiationPressure::new(&sun.name))
+        }
+        "j2" => Box::new(oblateness::Oblateness::new(&sun.name, 0.1, sun.radius)),
+        "tides" => Box::new(tides::Tides::new(&sun.name, 0.3, sun.radius, 0.1)),
+        _ => panic!("unknown force \"{}\", expected drag, radiation, j2 or tides", name),
+    };
+    sim.world.forces.push(force);
+}
+
+#[cfg(feature = "render")]
+// Load a model for every body in the simulation. `sync` sizes them by radius.
+fn setup_render(game: &mut natu::Natu, sim: &simulation::Simulation) {
+    game.camera.position.z = -4.0;
 
-    let mut accelerations: std::collections::HashMap<String, na::Vector3<f32>> = Default::default();
-    
-    {
-        // Reset all accelerations to 0
-        for (key,obj) in &mut game.objects {
-            obj.acceleration *= 0.0;
+    for name in &sim.world.bodies.names {
+        if name == "monkey" {
+            game.load_object("resources/suzanne.obj", "monkey", "resources/rock.png");
+        } else {
+            game.load_object("resources/suzanne.obj", name.as_str(), "resources/missing.png");
         }
+    }
+    game.sync(sim);
+}
 
-        for (key0,obj0) in &game.objects {
-            accelerations.insert(key0.to_string(), na::Vector3::<f32>::new(0.0,0.0,0.0));
-            let summer = accelerations.get_mut(key0).unwrap();
-
-            if obj0.mass != 0.0 {
-                for (key1,obj1) in &game.objects {
-                    if key0 != key1 {
-                        // use GMm/r^2 to calculate gravity
-                        let m = obj0.mass;
-                        let M = obj1.mass;
-                        let r2 = (obj0.position - obj1.position).magnitude().powf(2.0);
-                        let dir = (obj1.position - obj0.position).normalize();
-                        if r2 != 0.0 {
-                            *summer = *summer + dir*G*m*M/r2*(game.delta as f32);
-                        }
-                        
-                        //game.get(key0).
-                    }
-                }
-            } else {
-                *summer = obj0.acceleration;
+#[cfg(feature = "render")]
+fn tick(game: &mut natu::Natu, sim: &mut simulation::Simulation) {
+    select_integrator(game, sim);
+    sim.advance(game.delta);
+    handle_collisions(game, sim);
+    spectate(game, sim, "0");
+    game.sync(sim);
+    show_elements(game, sim, "0", "sun");
+}
+
+#[cfg(feature = "render")]
+// Drop the models of bodies which no longer exist
+fn handle_collisions(game: &mut natu::Natu, sim: &mut simulation::Simulation) {
+    use simulation::collision::Outcome;
+
+    for collision in sim.collision_events.drain(..) {
+        let [first, second] = &collision.bodies;
+        println!("t = {:.3}: {} and {} collided at {:.3}: {:?}", collision.time, first, second, collision.relative_speed, collision.outcome);
+        match &collision.outcome {
+            Outcome::Merged { survivor } => {
+                game.remove_object(if survivor == first { second } else { first });
             }
+            Outcome::Removed => {
+                game.remove_object(first);
+                game.remove_object(second);
+            }
+            Outcome::Bounced => {}
         }
+    }
+}
 
-        for (key,obj) in &mut game.objects {
-            println!("{}: {:?}",key, obj.acceleration);
-            obj.acceleration = obj.acceleration + accelerations.get(key).unwrap();
-        }
+#[cfg(feature = "render")]
+// Number keys switch integrator while running
+fn select_integrator(game: &mut natu::Natu, sim: &mut simulation::Simulation) {
+    use simulation::integrator::*;
+
+    let integrator: Box<dyn Integrator> = if game.window.get_key(glfw::Key::Num1) == glfw::Action::Press {
+        Box::new(SemiImplicitEuler)
+    } else if game.window.get_key(glfw::Key::Num2) == glfw::Action::Press {
+        Box::new(Leapfrog::new())
+    } else if game.window.get_key(glfw::Key::Num3) == glfw::Action::Press {
+        Box::new(VelocityVerlet::new())
+    } else if game.window.get_key(glfw::Key::Num4) == glfw::Action::Press {
+        Box::new(RungeKutta4)
+    } else if game.window.get_key(glfw::Key::Num5) == glfw::Action::Press {
+        Box::new(Yoshida4)
+    } else if game.window.get_key(glfw::Key::Num6) == glfw::Action::Press {
+        Box::new(block::BlockTimestep::new(block::Criterion::Acceleration { eta: 0.025 }))
+    } else if game.window.get_key(glfw::Key::Num7) == glfw::Action::Press {
+        Box::new(hermite::Hermite::with_block_timesteps(0.02))
+    } else if game.window.get_key(glfw::Key::Num8) == glfw::Action::Press {
+        Box::new(ias15::Ias15::new())
+    } else if game.window.get_key(glfw::Key::Num9) == glfw::Action::Press {
+        Box::new(wisdom_holman::WisdomHolman)
+    } else {
+        return;
+    };
 
-        
+    if integrator.name() != sim.integrator.name() {
+        println!("Integrator: {}", integrator.name());
+        sim.set_integrator(integrator);
     }
 }
 
-// Use delta and object's parameters (r, v, p, etc) to update its position
-fn apply_physics(game: &mut natu::Natu) {
-    for (_,obj) in &mut game.objects {
-        let delta = game.delta;
-        obj.velocity += delta as f32 * obj.acceleration;
-        obj.position += delta as f32 * obj.velocity;
-
-        // Just for fun: face in direction of movement
-        
-        
-        if obj.velocity.magnitude() != 0.0 {
-            let vel = obj.velocity.normalize();
-            obj.pitch = (-vel.y).asin();
-            obj.yaw = vel.x.atan2(vel.z);
-            println!("{:?}", obj.yaw);
-        }
-        
+#[cfg(feature = "render")]
+//
+fn spectate(game: &mut natu::Natu, sim: &simulation::Simulation, object_name: &str) {
+    // The body may have been merged away
+    let Some(i) = sim.world.bodies.index_of(object_name) else {
+        return;
+    };
+    if sim.world.bodies.velocity(i).magnitude() != 0.0 {
+        // Follow the interpolated render position so the camera doesn't jitter against the object.
+        // Centring the render origin on it keeps it precise however far it is from the origin.
+        game.origin = sim.interpolated_positions()[i];
+        game.camera.position = -game.camera.direction*10.0;
+    }
+}
+
+#[cfg(feature = "render")]
+// Osculating orbit of `name` about `primary` in the window title
+fn show_elements(game: &mut natu::Natu, sim: &simulation::Simulation, name: &str, primary: &str) {
+    let bodies = &sim.world.bodies;
+    let (Some(i), Some(primary)) = (bodies.index_of(name), bodies.index_of(primary)) else {
+        return;
+    };
+    let elements = sim.world.elements(i, primary);
+    game.window.set_title(&format!(
+        "{}: a = {:.4} e = {:.4} i = {:.2}\u{b0} \u{3a9} = {:.2}\u{b0} \u{3c9} = {:.2}\u{b0} M = {:.2}\u{b0}",
+        name,
+        elements.semi_major_axis,
+        elements.eccentricity,
+        elements.inclination.to_degrees(),
+        elements.longitude_of_ascending_node.to_degrees(),
+        elements.argument_of_periapsis.to_degrees(),
+        elements.mean_anomaly().to_degrees(),
+    ));
+}
+
+// Step the simulation without opening a window, e.g. on a build server
+fn run_headless(sim: &mut simulation::Simulation, steps: u64) {
+    for _ in 0..steps {
+        sim.step(sim.dt);
+    }
+
+    println!("t = {} after {} steps", sim.time, sim.steps);
+    let bodies = &sim.world.bodies;
+    for i in 0..bodies.len() {
+        println!("{}: {:?} {:?}", bodies.names[i], bodies.position(i), bodies.velocity(i));
+    }
+    if let Some(drift) = sim.diagnostics.drift() {
+        println!(
+            "drift: energy {:e} momentum {:e} angular momentum {:e} centre of mass {:e}",
+            drift.energy, drift.momentum, drift.angular_momentum, drift.centre_of_mass,
+        );
     }
-    
 }
 
 fn main() {
+    let mut sim = simulation::Simulation::new();
+
+    // Create bodies
+    setup(&mut sim);
+
+    // `natu --solver name` picks the gravity solver, see `select_solver`
+    let mut args: Vec<String> = std::env::args().collect();
+    if let Some(k) = args.iter().position(|arg| arg == "--solver") {
+        let name = args.get(k + 1).expect("--solver needs a name").clone();
+        select_solver(&mut sim, &name);
+        args.drain(k..k + 2);
+    }
+    // `natu --force name`, any number of times, adds forces, see `add_force`
+    while let Some(k) = args.iter().position(|arg| arg == "--force") {
+        let name = args.get(k + 1).expect("--force needs a name").clone();
+        add_force(&mut sim, &name);
+        args.drain(k..k + 2);
+    }
+
+    // `natu --headless [steps] [dt] [log]` runs the physics only, optionally writing the
+    // conserved quantities to the file `log`. Without the `render` feature that is all it can do,
+    // and `--headless` may be left out.
+    let headless = args.get(1).map(|arg| arg.as_str()) == Some("--headless");
+    if headless {
+        args.remove(1);
+    }
+    if headless || cfg!(not(feature = "render")) {
+        let steps = args.get(1).map_or(1000, |arg| arg.parse().expect("steps must be an integer"));
+        if let Some(dt) = args.get(2) {
+            sim.dt = dt.parse().expect("dt must be a number");
+        }
+        if let Some(path) = args.get(3) {
+            sim.diagnostics.log_to(path).expect("failed to create diagnostics log");
+        }
+        run_headless(&mut sim, steps);
+    } else {
+        #[cfg(feature = "render")]
+        run_window(sim);
+    }
+}
+
+#[cfg(feature = "render")]
+fn run_window(mut sim: simulation::Simulation) {
     let mut game = natu::Natu::init();
 
     // Load objects
-    setup(&mut game);
+    setup_render(&mut game, &sim);
 
     // Begin render loop
     while !game.window.should_close() {
         // Handle per-frame events such as physics
-        tick(&mut game);
+        tick(&mut game, &mut sim);
         game.update();
 
         // Enforce framerate
@@ -6,6 +6,8 @@ use crate::modelutils;
 use crate::shaderutils;
 use crate::object;
 use crate::camera;
+use crate::overlay;
+use crate::simulation;
 
 pub struct Natu {
     pub glfw: glfw::Glfw,
@@ -17,6 +19,12 @@ pub struct Natu {
     // Hashmap was chosen over vector to allow human readable object access
     // e.g. let object = objects.get_mut("monkey");
     pub objects: std::collections::HashMap<String, object::RenderObject>,
+    // Simulation position drawn at the render origin. Bodies are placed relative to it before
+    // narrowing to f32, so whatever is near it keeps full precision however far out it is.
+    pub origin: simulation::Vector,
+    // Conserved quantity drift plot, drawn over the scene. Filled in by `sync`.
+    pub overlay: overlay::Overlay,
+    pub show_overlay: bool,
 
     pub fps: f64,
     // Time between frames. Useful in physics calculations.
@@ -147,6 +155,36 @@ impl Natu {
         self.objects.get_mut(object_name).unwrap()
     }
 
+    pub fn remove_object(&mut self, object_name: &str) {
+        self.objects.remove(object_name);
+    }
+
+    // Copy body state from the simulation into the render objects of the same name. Bodies with a
+    // radius are drawn at that size. The simulation is only read from; bodies without a render
+    // object are ignored.
+    pub fn sync(&mut self, sim: &simulation::Simulation) {
+        let positions = sim.interpolated_positions();
+        let bodies = &sim.world.bodies;
+        for (i, position) in positions.into_iter().enumerate() {
+            if let Some(obj) = self.objects.get_mut(&bodies.names[i]) {
+                obj.position = (position - self.origin).cast::<f32>();
+                if bodies.radius[i] > 0.0 {
+                    obj.scale = bodies.radius[i] as f32;
+                }
+
+                // Just for fun: face in direction of movement
+                let velocity = bodies.velocity(i);
+                if velocity.magnitude() != 0.0 {
+                    let vel = velocity.normalize();
+                    obj.pitch = (-vel.y).asin() as f32;
+                    obj.yaw = vel.x.atan2(vel.z) as f32;
+                }
+            }
+        }
+
+        self.overlay.plot(&sim.diagnostics);
+    }
+
     pub fn render(&mut self) {
         unsafe {
             // Clear bits to set background colour and depth buffer check
@@ -154,6 +192,11 @@ impl Natu {
         }
         self.objects.iter()
             .for_each(|(_, object)| object.render(&self.shader_program));
+        if self.show_overlay {
+            self.overlay.render();
+            // Camera uniforms are set on whichever program is in use
+            self.shader_program.set_used();
+        }
         self.window.swap_buffers();
     }
 
@@ -208,6 +251,9 @@ impl Natu {
             camera: camera::Camera::new((width/height) as f32),
             shader_program: shader_program,
             objects: std::collections::HashMap::new(),
+            origin: simulation::Vector::zeros(),
+            overlay: overlay::Overlay::new(),
+            show_overlay: true,
 
             fps: 60.0,
             delta: 0.0,
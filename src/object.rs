@@ -11,12 +11,8 @@ pub struct RenderObject {
     pub yaw: f32,
 
     pub position: na::Vector3<f32>,
-    pub velocity: na::Vector3<f32>,
-    pub acceleration: na::Vector3<f32>,
 
     pub scale: f32,
-
-    pub mass: f32,
 }
 
 impl RenderObject {
@@ -42,10 +38,7 @@ impl RenderObject {
             model: model, 
             roll: 0.0, pitch: 0.0, yaw: 0.0,
             position: na::Vector3::<f32>::new(0.0, 0.0, 0.0),
-            velocity: na::Vector3::<f32>::new(0.0, 0.0, 0.0),
-            acceleration: na::Vector3::<f32>::new(0.0, 0.0, 0.0),
             scale: 0.5,
-            mass: 1.0,
         }
     }
 }
@@ -0,0 +1,145 @@
+use crate::shaderutils;
+use crate::simulation;
+
+// Corner of the screen the drift plot is drawn in, in normalised device coordinates
+const LEFT: f32 = 0.45;
+const RIGHT: f32 = 0.95;
+const BOTTOM: f32 = -0.95;
+const TOP: f32 = -0.45;
+// Range of the plot's log10 scale
+const LOWEST: f32 = -16.0;
+const HIGHEST: f32 = 0.0;
+
+// On-screen plot of the conserved quantity drift of a simulation: log10 of the relative energy
+// (red), momentum (blue) and angular momentum (green) errors over the measurements kept in
+// `Diagnostics::history`, with faint lines every four decades. Drawn on top of the scene as
+// plain coloured lines.
+pub struct Overlay {
+    program: shaderutils::Program,
+    vbo: gl::types::GLuint,
+    vao: gl::types::GLuint,
+
+    // Vertices in the buffer
+    vertex_count: i32,
+    // Step of the newest measurement plotted, to skip rebuilding an unchanged plot
+    plotted: Option<u64>,
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        let vert_shader = shaderutils::Shader::from_vert_source("./resources/shaders/coloured_vertices/shader.vert").unwrap();
+        let frag_shader = shaderutils::Shader::from_frag_source("./resources/shaders/coloured_vertices/shader.frag").unwrap();
+        let program = shaderutils::Program::from_shaders(&[vert_shader, frag_shader]).unwrap();
+
+        let mut overlay = Self { program, vbo: 0, vao: 0, vertex_count: 0, plotted: None };
+        unsafe {
+            gl::GenVertexArrays(1, &mut overlay.vao);
+            gl::GenBuffers(1, &mut overlay.vbo);
+
+            gl::BindVertexArray(overlay.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, overlay.vbo);
+
+            // Position then colour, 3 floats each
+            gl::VertexAttribPointer(
+                0, 3, gl::FLOAT, gl::FALSE,
+                (6* std::mem::size_of::<f32>()) as gl::types::GLint,
+                std::ptr::null(),
+            );
+            gl::EnableVertexAttribArray(0);
+            gl::VertexAttribPointer(
+                1, 3, gl::FLOAT, gl::FALSE,
+                (6* std::mem::size_of::<f32>()) as gl::types::GLint,
+                (3* std::mem::size_of::<f32>()) as *const gl::types::GLvoid,
+            );
+            gl::EnableVertexAttribArray(1);
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        overlay
+    }
+
+    // Rebuild the plot from the simulation's diagnostics, if there is anything new
+    pub fn plot(&mut self, diagnostics: &simulation::diagnostics::Diagnostics) {
+        let newest = diagnostics.latest().map(|measured| measured.step);
+        if newest == self.plotted {
+            return;
+        }
+        self.plotted = newest;
+
+        let mut vertices: Vec<f32> = vec![];
+        let mut line = |from: (f32, f32), to: (f32, f32), colour: [f32; 3]| {
+            vertices.extend_from_slice(&[from.0, from.1, 0.0]);
+            vertices.extend_from_slice(&colour);
+            vertices.extend_from_slice(&[to.0, to.1, 0.0]);
+            vertices.extend_from_slice(&colour);
+        };
+
+        // Frame and decade lines
+        let grey = [0.5, 0.5, 0.5];
+        line((LEFT, BOTTOM), (RIGHT, BOTTOM), grey);
+        line((RIGHT, BOTTOM), (RIGHT, TOP), grey);
+        line((RIGHT, TOP), (LEFT, TOP), grey);
+        line((LEFT, TOP), (LEFT, BOTTOM), grey);
+        let mut decade = LOWEST + 4.0;
+        while decade < HIGHEST {
+            let y = Self::y(10f64.powf(decade as f64));
+            line((LEFT, y), (RIGHT, y), [0.2, 0.2, 0.2]);
+            decade += 4.0;
+        }
+
+        // One segment per pair of neighbouring measurements, spread over the whole width
+        let drifts = diagnostics.drift_history();
+        let x = |i: usize| LEFT + (RIGHT - LEFT)*i as f32/(drifts.len() - 1) as f32;
+        for (i, pair) in drifts.windows(2).enumerate() {
+            let (from, to) = (&pair[0], &pair[1]);
+            line((x(i), Self::y(from.energy)), (x(i + 1), Self::y(to.energy)), [1.0, 0.2, 0.2]);
+            line((x(i), Self::y(from.momentum)), (x(i + 1), Self::y(to.momentum)), [0.3, 0.5, 1.0]);
+            line(
+                (x(i), Self::y(from.angular_momentum)),
+                (x(i + 1), Self::y(to.angular_momentum)),
+                [0.2, 1.0, 0.2],
+            );
+        }
+
+        self.vertex_count = (vertices.len()/6) as i32;
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (vertices.len() * std::mem::size_of::<f32>()) as gl::types::GLsizeiptr,
+                vertices.as_ptr() as *const gl::types::GLvoid,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    // Draw over whatever is already on screen. Leaves the overlay's shader program in use.
+    pub fn render(&self) {
+        self.program.set_used();
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::LINES, 0, self.vertex_count);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+    }
+
+    // Height of a relative error on the log scale, clamped to the plot. Exact zeros sit on the
+    // bottom edge.
+    fn y(error: f64) -> f32 {
+        let decades = (error.abs().log10() as f32).clamp(LOWEST, HIGHEST);
+        BOTTOM + (TOP - BOTTOM)*(decades - LOWEST)/(HIGHEST - LOWEST)
+    }
+}
@@ -0,0 +1,698 @@
+use nalgebra as na;
+
+pub mod bodies;
+pub mod collision;
+pub mod diagnostics;
+pub mod elements;
+pub mod force;
+pub mod gravity;
+pub mod integrator;
+pub mod kepler;
+pub mod ks;
+pub mod parallel;
+pub mod post_newtonian;
+pub mod regularisation;
+pub mod softening;
+pub mod units;
+
+// Headless simulation core. Nothing in here touches GLFW or OpenGL, so a `Simulation` can be
+// stepped on a machine without a display. `Natu` only reads body state from it to draw.
+
+// Scalar type used by all physics calculations. f64 keeps sub-metre precision at solar system
+// distances (~1e12 m); the renderer narrows to f32 relative to its origin. The
+// `single-precision` feature switches to f32 throughout.
+#[cfg(not(feature = "single-precision"))]
+pub type Real = f64;
+#[cfg(feature = "single-precision")]
+pub type Real = f32;
+pub type Vector = na::Vector3<Real>;
+
+pub use bodies::{Bodies, Body, Kind};
+
+// What a shift into the barycentric frame does with test particles. They don't affect where the
+// barycentre is either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MasslessFrame {
+    // Move them with everything else, so orbits about massive bodies are kept
+    Shift,
+    // Leave them where and as they are
+    Keep,
+}
+
+// All state needed to evaluate forces: the bodies themselves, the constants of the force law
+// and the solver which sums it.
+pub struct World {
+    pub bodies: Bodies,
+
+    pub gravity: gravity::Gravity,
+    pub solver: Box<dyn gravity::GravitySolver>,
+
+    // Side length of a periodic cube [0, size)^3 which bodies are wrapped back into after every
+    // step. None for open boundaries. Only the particle-mesh solver computes periodic forces.
+    pub periodic_box: Option<Real>,
+
+    // Threads used by the gravity solver. See `set_threads`.
+    pub pool: parallel::ThreadPool,
+
+    // How touching bodies are resolved after each step. None lets them pass through each other.
+    pub collisions: Option<collision::Response>,
+    pub broad_phase: collision::BroadPhase,
+
+    // Forces other than gravity, added to every acceleration evaluation
+    pub forces: Vec<Box<dyn force::Force>>,
+    // Relativistic corrections added to gravity. None for Newtonian gravity only.
+    pub post_newtonian: Option<post_newtonian::PostNewtonian>,
+
+    // Automatic KS regularisation of hard binaries. None integrates every pair directly.
+    pub regularisation: Option<regularisation::Regularisation>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            bodies: Bodies::new(),
+            gravity: gravity::Gravity::new(),
+            solver: Box::new(gravity::DirectSum),
+            periodic_box: None,
+            pool: parallel::ThreadPool::default(),
+            collisions: None,
+            broad_phase: collision::BroadPhase::SweepAndPrune,
+            forces: vec![],
+            post_newtonian: None,
+            regularisation: None,
+        }
+    }
+
+    // Number of threads for force evaluation, 0 for one per core in the pool shared by all worlds.
+    // Only has an effect with the `parallel` feature. Results do not depend on the thread count.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.pool = parallel::ThreadPool::new(threads);
+    }
+
+    // Returns the index of the new body
+    pub fn add_body(&mut self, body: Body) -> usize {
+        self.bodies.push(body)
+    }
+
+    // Snapshot of the body called `name`
+    pub fn find(&self, name: &str) -> Option<Body> {
+        self.bodies.index_of(name).map(|i| self.bodies.get(i))
+    }
+
+    // Add `body` on the orbit `elements` about the existing body `primary`, moving with it. The
+    // orbit is that of the two bodies alone, with mu = G*(m_primary + m_body).
+    pub fn add_body_from_elements(&mut self, mut body: Body, primary: &str, elements: &elements::Elements) -> usize {
+        let primary = self.bodies.index_of(primary).expect("primary body does not exist");
+        let mass = if body.kind.is_test_particle() { 0.0 } else { body.mass };
+        let mu = self.gravity.g*(self.bodies.mass[primary] + mass);
+        let (position, velocity) = elements.to_state(mu);
+        body.position = self.bodies.position(primary) + position;
+        body.velocity = self.bodies.velocity(primary) + velocity;
+        self.add_body(body)
+    }
+
+    // Osculating elements of body `i` about body `primary`
+    pub fn elements(&self, i: usize, primary: usize) -> elements::Elements {
+        let mu = self.gravity.g*(self.bodies.mass[primary] + self.bodies.mass[i]);
+        elements::Elements::from_state(
+            self.bodies.position(i) - self.bodies.position(primary),
+            self.bodies.velocity(i) - self.bodies.velocity(primary),
+            mu,
+        )
+    }
+
+    // Gravity between all bodies: a_i = sum over j of G*m_j/r_ij^2 towards j, softened by
+    // `gravity.kernel` and summed by `solver`, plus any post-Newtonian terms and `forces`. Pinned
+    // bodies get no acceleration, and both members of a regularised pair get that of their centre
+    // of mass.
+    pub fn nbody(&mut self) {
+        let (solver, bodies, gravity) = (&mut self.solver, &self.bodies, &self.gravity);
+        let mut accelerations = self.pool.install(|| solver.accelerations(bodies, gravity));
+        let all: Vec<usize> = (0..self.bodies.len()).collect();
+        self.add_extra_accelerations(&all, &mut accelerations);
+        self.average_regularised(&all, &mut accelerations);
+
+        for (i, acceleration) in accelerations.into_iter().enumerate() {
+            self.set_acceleration_unless_pinned(i, acceleration);
+        }
+    }
+
+    // As `nbody`, but only updates the bodies in `active`, and the partners of any regularised
+    // ones. Forces still come from every body.
+    pub fn nbody_of(&mut self, active: &[usize]) {
+        let targets = self.with_partners(active);
+        let (solver, bodies, gravity) = (&mut self.solver, &self.bodies, &self.gravity);
+        let mut accelerations = self.pool.install(|| solver.accelerations_of(&targets, bodies, gravity));
+        self.add_extra_accelerations(&targets, &mut accelerations);
+        self.average_regularised(&targets, &mut accelerations);
+
+        for (i, acceleration) in targets.iter().zip(accelerations) {
+            self.set_acceleration_unless_pinned(*i, acceleration);
+        }
+    }
+
+    // As `nbody_of`, also returning the jerk (da/dt) of each body in `active`. Jerk needs every
+    // pair's relative velocity, which the approximate solvers don't keep, so this always sums
+    // directly whatever `solver` is. Pinned bodies get zero jerk. Post-Newtonian terms and
+    // `forces` are added to the acceleration but left out of the jerk.
+    pub fn nbody_jerk_of(&mut self, active: &[usize]) -> Vec<Vector> {
+        let targets = self.with_partners(active);
+        let (bodies, gravity) = (&self.bodies, &self.gravity);
+        let (mut accelerations, mut jerks): (Vec<Vector>, Vec<Vector>) = self.pool
+            .install(|| gravity::DirectSum.accelerations_and_jerks(&targets, bodies, gravity))
+            .into_iter()
+            .unzip();
+        self.add_extra_accelerations(&targets, &mut accelerations);
+        self.average_regularised(&targets, &mut accelerations);
+        self.average_regularised(&targets, &mut jerks);
+
+        for (i, acceleration) in targets.iter().zip(accelerations) {
+            self.set_acceleration_unless_pinned(*i, acceleration);
+        }
+        // Partners were only added on the end
+        active.iter().zip(jerks)
+            .map(|(i, jerk)| if self.bodies.kind[*i].is_pinned() { Vector::zeros() } else { jerk })
+            .collect()
+    }
+
+    // Add the post-Newtonian corrections, if enabled, and `forces` to the Newtonian
+    // `accelerations` of `targets`
+    fn add_extra_accelerations(&self, targets: &[usize], accelerations: &mut [Vector]) {
+        let (bodies, gravity) = (&self.bodies, &self.gravity);
+        if let Some(terms) = self.post_newtonian {
+            let corrections = self.pool.install(|| post_newtonian::accelerations(terms, targets, accelerations, bodies, gravity));
+            for (acceleration, correction) in accelerations.iter_mut().zip(corrections) {
+                *acceleration += correction;
+            }
+        }
+        for force in &self.forces {
+            for (acceleration, extra) in accelerations.iter_mut().zip(force.accelerations(targets, bodies, gravity)) {
+                *acceleration += extra;
+            }
+        }
+    }
+
+    // `active` followed by the regularised partners of any bodies in it which are missing
+    fn with_partners(&self, active: &[usize]) -> Vec<usize> {
+        let mut targets = active.to_vec();
+        if let Some(regularisation) = &self.regularisation {
+            for (i, j) in regularisation.members(self) {
+                match (active.contains(&i), active.contains(&j)) {
+                    (true, false) => targets.push(j),
+                    (false, true) => targets.push(i),
+                    _ => (),
+                }
+            }
+        }
+        targets
+    }
+
+    // Replace the values (accelerations or jerks) of both members of each regularised pair in
+    // `targets` with their mass-weighted mean. The pair's own pull on itself cancels out of it.
+    fn average_regularised(&self, targets: &[usize], values: &mut [Vector]) {
+        let Some(regularisation) = &self.regularisation else {
+            return;
+        };
+        for (i, j) in regularisation.members(self) {
+            let (Some(a), Some(b)) = (targets.iter().position(|k| *k == i), targets.iter().position(|k| *k == j)) else {
+                continue;
+            };
+            let (mi, mj) = (self.bodies.mass[i], self.bodies.mass[j]);
+            let mean = (mi*values[a] + mj*values[b])/(mi + mj);
+            values[a] = mean;
+            values[b] = mean;
+        }
+    }
+
+    fn set_acceleration_unless_pinned(&mut self, i: usize, acceleration: Vector) {
+        if self.bodies.kind[i].is_pinned() {
+            self.bodies.set_acceleration(i, Vector::zeros());
+        } else {
+            self.bodies.set_acceleration(i, acceleration);
+        }
+    }
+
+    // Move pinned bodies with paths to where they are at `time`
+    pub fn follow_paths(&mut self, time: f64) {
+        for i in 0..self.bodies.len() {
+            if let Kind::Pinned(Some(path)) = &self.bodies.kind[i] {
+                let (position, velocity) = path(time);
+                self.bodies.set_position(i, position);
+                self.bodies.set_velocity(i, velocity);
+            }
+        }
+    }
+
+    // Position and velocity of the centre of mass of the dynamic bodies, or None if they have no
+    // mass. Pinned bodies are held by something outside the world, so they don't count.
+    pub fn barycentre(&self) -> Option<(Vector, Vector)> {
+        let b = &self.bodies;
+        let mut mass = 0.0;
+        let mut position = Vector::zeros();
+        let mut velocity = Vector::zeros();
+        for i in (0..b.len()).filter(|i| b.kind[*i].is_dynamic()) {
+            mass += b.mass[i];
+            position += b.mass[i]*b.position(i);
+            velocity += b.mass[i]*b.velocity(i);
+        }
+        if mass == 0.0 {
+            return None;
+        }
+        Some((position/mass, velocity/mass))
+    }
+
+    // Whether a shift of frame applies to body `i`. Pinned bodies never move with the frame.
+    pub fn moves_with_frame(&self, i: usize, massless: MasslessFrame) -> bool {
+        match self.bodies.kind[i] {
+            Kind::Dynamic => true,
+            Kind::TestParticle => massless == MasslessFrame::Shift,
+            Kind::Pinned(_) => false,
+        }
+    }
+
+    // Move into the frame where the centre of mass is at rest at the origin, so the system as a
+    // whole stays put. In a periodic box only velocities change, as position there is relative.
+    // Returns the position and velocity removed from every body that moved.
+    pub fn shift_to_barycentre(&mut self, massless: MasslessFrame) -> (Vector, Vector) {
+        let Some((mut position, velocity)) = self.barycentre() else {
+            return (Vector::zeros(), Vector::zeros());
+        };
+        if self.periodic_box.is_some() {
+            position = Vector::zeros();
+        }
+
+        for i in 0..self.bodies.len() {
+            if self.moves_with_frame(i, massless) {
+                self.bodies.set_position(i, self.bodies.position(i) - position);
+                self.bodies.set_velocity(i, self.bodies.velocity(i) - velocity);
+            }
+        }
+        (position, velocity)
+    }
+
+    // Put every body back inside the periodic box, if there is one
+    pub fn wrap_positions(&mut self) {
+        if let Some(size) = self.periodic_box {
+            for component in [&mut self.bodies.x, &mut self.bodies.y, &mut self.bodies.z] {
+                component.iter_mut().for_each(|x| *x = x.rem_euclid(size));
+            }
+        }
+    }
+
+    // Shortest offset from `from` to `to`, taking the periodic box into account
+    pub fn separation(&self, from: &Vector, to: &Vector) -> Vector {
+        let dx = to - from;
+        match self.periodic_box {
+            Some(size) => dx.map(|x| x - size*(x/size).round()),
+            None => dx,
+        }
+    }
+
+    // Move every body along its velocity ("drift")
+    pub fn drift(&mut self, delta: Real) {
+        let b = &mut self.bodies;
+        for (x, v) in [(&mut b.x, &b.vx), (&mut b.y, &b.vy), (&mut b.z, &b.vz)] {
+            x.iter_mut().zip(v).for_each(|(x, v)| *x += delta*v);
+        }
+    }
+
+    // Change every body's velocity by its current acceleration ("kick")
+    pub fn kick(&mut self, delta: Real) {
+        let b = &mut self.bodies;
+        for (v, a) in [(&mut b.vx, &b.ax), (&mut b.vy, &b.ay), (&mut b.vz, &b.az)] {
+            v.iter_mut().zip(a).for_each(|(v, a)| *v += delta*a);
+        }
+    }
+
+    // Snapshots of body state in body order. Used by integrators which need to evaluate
+    // accelerations away from the current state (e.g. RK4 stages).
+    pub fn positions(&self) -> Vec<Vector> {
+        (0..self.bodies.len()).map(|i| self.bodies.position(i)).collect()
+    }
+
+    pub fn velocities(&self) -> Vec<Vector> {
+        (0..self.bodies.len()).map(|i| self.bodies.velocity(i)).collect()
+    }
+
+    pub fn accelerations(&self) -> Vec<Vector> {
+        (0..self.bodies.len()).map(|i| self.bodies.acceleration(i)).collect()
+    }
+
+    pub fn set_positions(&mut self, positions: &[Vector]) {
+        for (i, position) in positions.iter().enumerate() {
+            self.bodies.set_position(i, *position);
+        }
+    }
+
+    pub fn set_velocities(&mut self, velocities: &[Vector]) {
+        for (i, velocity) in velocities.iter().enumerate() {
+            self.bodies.set_velocity(i, *velocity);
+        }
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Simulation {
+    pub world: World,
+    // Scheme used to advance the world. May be swapped at any point between steps.
+    pub integrator: Box<dyn integrator::Integrator>,
+
+    // Fixed physics timestep. Independent of the render frame rate so runs are deterministic.
+    pub dt: f64,
+    // Most steps run for a single call to `advance`. Any further time is dropped, so a stalled
+    // frame (e.g. while the window is dragged) slows the simulation down instead of blowing it up.
+    pub max_substeps: u32,
+    // Frame time which has not been simulated yet
+    accumulator: f64,
+    // Body positions before the latest step, for interpolating between physics states
+    previous_positions: Vec<Vector>,
+
+    // Simulated time elapsed and number of steps taken so far
+    pub time: f64,
+    pub steps: u64,
+
+    // Move back into the barycentric frame every this many steps, 0 for never. Stops round-off
+    // in the total momentum from slowly carrying the system away. See `recentre`.
+    pub recentre_interval: u64,
+    pub recentre_massless: MasslessFrame,
+
+    // Conserved quantity tracking. Off until `diagnostics.interval` is set.
+    pub diagnostics: diagnostics::Diagnostics,
+
+    // Collisions resolved since the caller last emptied this. Merged and removed bodies are gone
+    // from the world by the time they show up here.
+    pub collision_events: Vec<collision::Collision>,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self {
+            world: World::new(),
+            integrator: Box::new(integrator::SemiImplicitEuler),
+            dt: 1.0/240.0,
+            max_substeps: 16,
+            accumulator: 0.0,
+            previous_positions: vec![],
+            time: 0.0,
+            steps: 0,
+            recentre_interval: 0,
+            recentre_massless: MasslessFrame::Shift,
+            diagnostics: diagnostics::Diagnostics::new(),
+            collision_events: vec![],
+        }
+    }
+
+    pub fn set_integrator(&mut self, integrator: Box<dyn integrator::Integrator>) {
+        self.integrator = integrator;
+    }
+
+    // Advance the simulation by a single step of `delta` seconds
+    pub fn step(&mut self, delta: f64) {
+        // Reference state for drift, taken before the first step after diagnostics are enabled
+        if self.diagnostics.interval > 0 && self.diagnostics.initial().is_none() {
+            self.diagnostics.record(&self.world, self.time, self.steps);
+        }
+
+        self.world.follow_paths(self.time);
+        self.previous_positions = self.world.positions();
+        regularisation::begin_step(&mut self.world, delta as Real);
+        // Pinned bodies with paths drift along their velocity during the step, then are put
+        // exactly on the path at its end
+        self.integrator.step(&mut self.world, delta as Real);
+        self.world.follow_paths(self.time + delta);
+        regularisation::end_step(&mut self.world, delta as Real);
+        self.world.wrap_positions();
+
+        if let Some(response) = self.world.collisions {
+            let before = self.world.bodies.len();
+            let broad_phase = self.world.broad_phase;
+            let collisions = collision::resolve(
+                &mut self.world, response, broad_phase, &self.previous_positions, self.time, delta as Real,
+            );
+            // Indices have moved, so there is nothing to interpolate from until the next step
+            if self.world.bodies.len() != before {
+                self.previous_positions.clear();
+            }
+            self.collision_events.extend(collisions);
+        }
+
+        self.time += delta;
+        self.steps += 1;
+
+        if self.recentre_interval > 0 && self.steps.is_multiple_of(self.recentre_interval) {
+            self.recentre();
+        }
+
+        if self.diagnostics.interval > 0 && self.steps.is_multiple_of(self.diagnostics.interval) {
+            self.diagnostics.record(&self.world, self.time, self.steps);
+        }
+    }
+
+    // Shift the world into the barycentric frame, e.g. once after setting it up so randomly
+    // placed bodies don't drift away together. Test particles follow `recentre_massless`. Any
+    // momentum removed shows up in the diagnostics' momentum drift.
+    pub fn recentre(&mut self) {
+        let (position, _) = self.world.shift_to_barycentre(self.recentre_massless);
+
+        // Interpolate within the new frame so the render doesn't jump
+        for (i, previous) in self.previous_positions.iter_mut().enumerate() {
+            if self.world.moves_with_frame(i, self.recentre_massless) {
+                *previous -= position;
+            }
+        }
+    }
+
+    // Run as many fixed `dt` steps as fit into the elapsed frame time (up to `max_substeps`).
+    // Returns the number of steps taken.
+    pub fn advance(&mut self, frame_delta: f64) -> u32 {
+        self.accumulator += frame_delta;
+
+        let mut substeps = 0;
+        while self.accumulator >= self.dt && substeps < self.max_substeps {
+            self.step(self.dt);
+            self.accumulator -= self.dt;
+            substeps += 1;
+        }
+
+        // Catch-up cap reached: forget the time we could not simulate
+        if self.accumulator >= self.dt {
+            self.accumulator %= self.dt;
+        }
+
+        substeps
+    }
+
+    // How far the leftover frame time is between the previous and current physics state (0..1)
+    pub fn interpolation_alpha(&self) -> Real {
+        (self.accumulator/self.dt) as Real
+    }
+
+    // Body positions blended between the last two physics states, in body order.
+    // Rendering these instead of the raw positions avoids stutter when the frame rate and
+    // physics rate don't divide evenly.
+    pub fn interpolated_positions(&self) -> Vec<Vector> {
+        let alpha = self.interpolation_alpha();
+        (0..self.world.bodies.len())
+            .map(|i| {
+                let position = self.world.bodies.position(i);
+                match self.previous_positions.get(i) {
+                    // Bodies wrapped round a periodic box move the short way
+                    Some(previous) => previous + self.world.separation(previous, &position)*alpha,
+                    None => position,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Unit mass at the origin and a body of `mass` at pericentre of an orbit with semi-major axis
+    // 1 and `eccentricity` about it, in the barycentric frame with G = 1. An orbit takes
+    // 2*pi/sqrt(1 + mass).
+    pub(crate) fn two_body(mass: Real, eccentricity: Real) -> World {
+        let mut world = World::new();
+        let mut sun = Body::new("sun");
+        sun.mass = 1.0;
+        world.add_body(sun);
+        let mut planet = Body::new("planet");
+        planet.mass = mass;
+        let elements = elements::Elements {
+            semi_major_axis: 1.0,
+            eccentricity,
+            inclination: 0.3,
+            longitude_of_ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            true_anomaly: 0.0,
+        };
+        world.add_body_from_elements(planet, "sun", &elements);
+        world.shift_to_barycentre(MasslessFrame::Shift);
+        world
+    }
+
+    // `n` bodies of equal mass, adding up to 1, spread uniformly through a unit sphere. The same
+    // bodies for the same `seed`.
+    pub(crate) fn cluster(n: usize, seed: u64) -> Bodies {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut bodies = Bodies::new();
+        while bodies.len() < n {
+            let position = Vector::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            if position.magnitude() > 1.0 {
+                continue;
+            }
+            let mut body = Body::new(&bodies.len().to_string());
+            body.mass = 1.0/n as Real;
+            body.position = position;
+            bodies.push(body);
+        }
+        bodies
+    }
+
+    // Position and velocity of the planet of `two_body` about the sun
+    pub(crate) fn relative(world: &World) -> (Vector, Vector) {
+        let b = &world.bodies;
+        (b.position(1) - b.position(0), b.velocity(1) - b.velocity(0))
+    }
+
+    pub(crate) fn energy(world: &World) -> f64 {
+        diagnostics::Conserved::measure(world, 0.0, 0).energy()
+    }
+
+    pub(crate) fn period(mass: Real) -> Real {
+        std::f64::consts::TAU as Real/(1.0 + mass).sqrt()
+    }
+
+    #[test]
+    fn fixed_steps_from_frame_time() {
+        let mut sim = Simulation::new();
+        sim.world = two_body(1e-3, 0.0);
+        sim.dt = 0.01;
+
+        assert_eq!(sim.advance(0.035), 3);
+        assert!((sim.interpolation_alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(sim.advance(0.004), 0);
+        assert_eq!(sim.advance(0.001), 1);
+
+        // A stalled frame runs at most `max_substeps` and drops the rest
+        assert_eq!(sim.advance(1.0), sim.max_substeps);
+        assert!(sim.interpolation_alpha() <= 1.0);
+        assert_eq!(sim.steps, 4 + sim.max_substeps as u64);
+    }
+
+    // `two_body` moved and set moving, with a test particle and a pinned body alongside
+    fn offset_world() -> World {
+        let mut world = two_body(1e-3, 0.5);
+        let (offset, drift) = (Vector::new(1.0, 2.0, 3.0), Vector::new(0.1, 0.0, -0.2));
+        for i in 0..world.bodies.len() {
+            world.bodies.set_position(i, world.bodies.position(i) + offset);
+            world.bodies.set_velocity(i, world.bodies.velocity(i) + drift);
+        }
+        let mut particle = Body::new("particle");
+        particle.kind = Kind::TestParticle;
+        particle.position = offset;
+        world.add_body(particle);
+        let mut pinned = Body::new("pinned");
+        pinned.kind = Kind::Pinned(None);
+        pinned.mass = 0.0;
+        pinned.position = offset;
+        world.add_body(pinned);
+        world
+    }
+
+    #[test]
+    fn recentre_on_barycentre() {
+        for massless in [MasslessFrame::Shift, MasslessFrame::Keep] {
+            let mut sim = Simulation::new();
+            sim.world = offset_world();
+            sim.recentre_massless = massless;
+            sim.recentre();
+
+            let (position, velocity) = sim.world.barycentre().unwrap();
+            assert!(position.magnitude() < 1e2*Real::EPSILON && velocity.magnitude() < 1e2*Real::EPSILON);
+            let particle = sim.world.bodies.position(2);
+            match massless {
+                MasslessFrame::Shift => assert!(particle.magnitude() < 1e2*Real::EPSILON, "{:?}", particle),
+                MasslessFrame::Keep => assert_eq!(particle, Vector::new(1.0, 2.0, 3.0)),
+            }
+            assert_eq!(sim.world.bodies.position(3), Vector::new(1.0, 2.0, 3.0));
+        }
+    }
+
+    #[test]
+    fn pinned_bodies_stay_or_follow_paths() {
+        let mut sim = Simulation::new();
+        sim.set_integrator(Box::new(integrator::Leapfrog::new()));
+        let mut anchor = Body::new("anchor");
+        anchor.kind = Kind::Pinned(None);
+        anchor.position = Vector::new(1.0, 0.0, 0.0);
+        sim.world.add_body(anchor);
+        let mut mover = Body::new("mover");
+        mover.kind = Kind::Pinned(Some(std::sync::Arc::new(|t| {
+            let t = t as Real;
+            (Vector::new(t.cos(), t.sin(), 0.0), Vector::new(-t.sin(), t.cos(), 0.0))
+        })));
+        sim.world.add_body(mover);
+        sim.world.add_body(Body::new("free"));
+
+        for _ in 0..100 {
+            sim.step(0.01);
+        }
+        let bodies = &sim.world.bodies;
+        assert_eq!(bodies.position(0), Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(bodies.velocity(0), Vector::zeros());
+        let t = sim.time as Real;
+        assert_eq!(bodies.position(1), Vector::new(t.cos(), t.sin(), 0.0));
+        // Pulled by both, which are pulled by nothing
+        assert!(bodies.position(2).magnitude() > 0.1);
+        assert_eq!(bodies.acceleration(0), Vector::zeros());
+        assert_eq!(bodies.acceleration(1), Vector::zeros());
+    }
+
+    #[test]
+    fn test_particles_pull_nothing() {
+        let mut world = two_body(1e-3, 0.5);
+        world.nbody();
+        let accelerations = world.accelerations();
+
+        let mut particle = Body::new("particle");
+        particle.kind = Kind::TestParticle;
+        particle.mass = 5.0;
+        particle.position = Vector::new(0.0, 0.5, 0.0);
+        let i = world.add_body(particle);
+        world.nbody();
+
+        assert_eq!(world.bodies.mass[i], 0.0);
+        assert_eq!(world.accelerations()[..i], accelerations[..]);
+        // But feels the others
+        assert!(world.bodies.acceleration(i).magnitude() > 1.0);
+    }
+
+    #[test]
+    fn recentre_in_periodic_box() {
+        let mut world = offset_world();
+        world.periodic_box = Some(10.0);
+        let before = world.positions();
+        let (position, velocity) = world.shift_to_barycentre(MasslessFrame::Shift);
+
+        // Only the velocity is relative to anything
+        assert_eq!(position, Vector::zeros());
+        assert!(velocity.magnitude() > 0.0);
+        assert_eq!(world.positions(), before);
+        assert!(world.barycentre().unwrap().1.magnitude() < 1e2*Real::EPSILON);
+    }
+}
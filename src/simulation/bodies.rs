@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use crate::simulation::{Real, Vector};
+
+// Scripted motion of a pinned body: position and velocity at a simulation time
+pub type Path = Arc<dyn Fn(f64) -> (Vector, Vector) + Send + Sync>;
+
+// How a body takes part in the dynamics
+#[derive(Clone, Default)]
+pub enum Kind {
+    // Moves under gravity and pulls on everything else
+    #[default]
+    Dynamic,
+    // Moves under gravity but pulls on nothing. Test particles are massless: any mass given is
+    // ignored.
+    TestParticle,
+    // Pulls on everything else but is never moved by it. Stays where it is, or follows the path
+    // if there is one.
+    Pinned(Option<Path>),
+}
+
+impl Kind {
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self, Kind::Dynamic)
+    }
+
+    pub fn is_test_particle(&self) -> bool {
+        matches!(self, Kind::TestParticle)
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        matches!(self, Kind::Pinned(_))
+    }
+}
+
+impl std::fmt::Debug for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Kind::Dynamic => write!(f, "Dynamic"),
+            Kind::TestParticle => write!(f, "TestParticle"),
+            Kind::Pinned(None) => write!(f, "Pinned"),
+            Kind::Pinned(Some(_)) => write!(f, "Pinned(path)"),
+        }
+    }
+}
+
+// A single body, by value. Used to describe bodies when adding them and to hand out snapshots;
+// the world itself stores bodies as `Bodies`.
+#[derive(Clone, Debug)]
+pub struct Body {
+    pub name: String,
+
+    pub position: Vector,
+    pub velocity: Vector,
+    pub acceleration: Vector,
+
+    pub mass: Real,
+    // Overrides the world's softening length for forces involving this body
+    pub softening: Option<Real>,
+    pub kind: Kind,
+    // Size of the body for collisions. Bodies with zero radius never collide.
+    pub radius: Real,
+    // Ratio of the push of `RadiationPressure` to the pull of gravity from its source, beta.
+    // Zero for bodies light has no effect on.
+    pub radiation_pressure: Real,
+}
+
+impl Body {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            position: Vector::zeros(),
+            velocity: Vector::zeros(),
+            acceleration: Vector::zeros(),
+            mass: 1.0,
+            softening: None,
+            kind: Kind::Dynamic,
+            radius: 0.0,
+            radiation_pressure: 0.0,
+        }
+    }
+}
+
+// Body state stored as structure of arrays: one contiguous vector per component. The force
+// kernels stream through x, y, z and mass for every pair, so keeping each in its own array
+// reads only what they need and keeps it contiguous in cache.
+//
+// Bodies keep the order they were added in, so runs are reproducible. Names are looked up through
+// a separate index.
+#[derive(Clone, Default)]
+pub struct Bodies {
+    pub x: Vec<Real>,
+    pub y: Vec<Real>,
+    pub z: Vec<Real>,
+
+    pub vx: Vec<Real>,
+    pub vy: Vec<Real>,
+    pub vz: Vec<Real>,
+
+    pub ax: Vec<Real>,
+    pub ay: Vec<Real>,
+    pub az: Vec<Real>,
+
+    // Source of gravity, so always zero for test particles
+    pub mass: Vec<Real>,
+    pub softening: Vec<Option<Real>>,
+    pub kind: Vec<Kind>,
+    pub radius: Vec<Real>,
+    pub radiation_pressure: Vec<Real>,
+
+    pub names: Vec<String>,
+    index: std::collections::HashMap<String, usize>,
+}
+
+impl Bodies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    // Returns the index of the new body. Names must be unique.
+    pub fn push(&mut self, body: Body) -> usize {
+        assert!(!self.index.contains_key(&body.name), "body \"{}\" already exists", body.name);
+        let i = self.len();
+        self.index.insert(body.name.clone(), i);
+
+        self.x.push(body.position.x);
+        self.y.push(body.position.y);
+        self.z.push(body.position.z);
+        self.vx.push(body.velocity.x);
+        self.vy.push(body.velocity.y);
+        self.vz.push(body.velocity.z);
+        self.ax.push(body.acceleration.x);
+        self.ay.push(body.acceleration.y);
+        self.az.push(body.acceleration.z);
+        self.mass.push(if body.kind.is_test_particle() { 0.0 } else { body.mass });
+        self.softening.push(body.softening);
+        self.kind.push(body.kind);
+        self.radius.push(body.radius);
+        self.radiation_pressure.push(body.radiation_pressure);
+        self.names.push(body.name);
+        i
+    }
+
+    // Remove body `i`, keeping the order of the rest. Later bodies move down one index.
+    pub fn remove(&mut self, i: usize) -> Body {
+        let body = self.get(i);
+
+        for component in [
+            &mut self.x, &mut self.y, &mut self.z,
+            &mut self.vx, &mut self.vy, &mut self.vz,
+            &mut self.ax, &mut self.ay, &mut self.az,
+            &mut self.mass, &mut self.radius, &mut self.radiation_pressure,
+        ] {
+            component.remove(i);
+        }
+        self.softening.remove(i);
+        self.kind.remove(i);
+        self.names.remove(i);
+
+        self.index.remove(&body.name);
+        for name in &self.names[i..] {
+            *self.index.get_mut(name).unwrap() -= 1;
+        }
+        body
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    // Snapshot of body `i`
+    pub fn get(&self, i: usize) -> Body {
+        Body {
+            name: self.names[i].clone(),
+            position: self.position(i),
+            velocity: self.velocity(i),
+            acceleration: self.acceleration(i),
+            mass: self.mass[i],
+            softening: self.softening[i],
+            kind: self.kind[i].clone(),
+            radius: self.radius[i],
+            radiation_pressure: self.radiation_pressure[i],
+        }
+    }
+
+    // Overwrite body `i` with `body`, which must have the same name
+    pub fn set(&mut self, i: usize, body: Body) {
+        assert_eq!(self.names[i], body.name, "bodies can't be renamed");
+        self.set_position(i, body.position);
+        self.set_velocity(i, body.velocity);
+        self.set_acceleration(i, body.acceleration);
+        self.mass[i] = if body.kind.is_test_particle() { 0.0 } else { body.mass };
+        self.softening[i] = body.softening;
+        self.kind[i] = body.kind;
+        self.radius[i] = body.radius;
+        self.radiation_pressure[i] = body.radiation_pressure;
+    }
+
+    pub fn position(&self, i: usize) -> Vector {
+        Vector::new(self.x[i], self.y[i], self.z[i])
+    }
+
+    pub fn velocity(&self, i: usize) -> Vector {
+        Vector::new(self.vx[i], self.vy[i], self.vz[i])
+    }
+
+    pub fn acceleration(&self, i: usize) -> Vector {
+        Vector::new(self.ax[i], self.ay[i], self.az[i])
+    }
+
+    pub fn set_position(&mut self, i: usize, position: Vector) {
+        self.x[i] = position.x;
+        self.y[i] = position.y;
+        self.z[i] = position.z;
+    }
+
+    pub fn set_velocity(&mut self, i: usize, velocity: Vector) {
+        self.vx[i] = velocity.x;
+        self.vy[i] = velocity.y;
+        self.vz[i] = velocity.z;
+    }
+
+    pub fn set_acceleration(&mut self, i: usize, acceleration: Vector) {
+        self.ax[i] = acceleration.x;
+        self.ay[i] = acceleration.y;
+        self.az[i] = acceleration.z;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(name: &str, x: Real) -> Body {
+        let mut body = Body::new(name);
+        body.position = Vector::new(x, 2.0*x, 3.0*x);
+        body.velocity = Vector::new(-x, 0.0, x);
+        body.mass = x;
+        body.softening = Some(0.1*x);
+        body.radius = 0.5*x;
+        body.radiation_pressure = 0.2*x;
+        body
+    }
+
+    #[test]
+    fn get_returns_what_was_pushed() {
+        let mut bodies = Bodies::new();
+        for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+            assert_eq!(bodies.push(body(name, i as Real + 1.0)), i);
+        }
+        let b = bodies.get(1);
+        let expected = body("b", 2.0);
+        assert_eq!((b.name.as_str(), b.position, b.velocity, b.mass), ("b", expected.position, expected.velocity, 2.0));
+        assert_eq!((b.softening, b.radius, b.radiation_pressure), (expected.softening, expected.radius, expected.radiation_pressure));
+
+        let mut changed = body("b", 5.0);
+        changed.kind = Kind::TestParticle;
+        bodies.set(1, changed);
+        assert_eq!(bodies.position(1), Vector::new(5.0, 10.0, 15.0));
+        // Test particles never have mass
+        assert_eq!(bodies.mass[1], 0.0);
+    }
+
+    #[test]
+    fn remove_keeps_order() {
+        let mut bodies = Bodies::new();
+        for (i, name) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            bodies.push(body(name, i as Real + 1.0));
+        }
+        assert_eq!(bodies.remove(1).name, "b");
+
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies.names, ["a", "c", "d"]);
+        assert_eq!(bodies.index_of("b"), None);
+        assert_eq!([bodies.index_of("a"), bodies.index_of("c"), bodies.index_of("d")], [Some(0), Some(1), Some(2)]);
+        assert_eq!(bodies.mass, [1.0, 3.0, 4.0]);
+        assert_eq!(bodies.radius, [0.5, 1.5, 2.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already exists")]
+    fn names_are_unique() {
+        let mut bodies = Bodies::new();
+        bodies.push(Body::new("a"));
+        bodies.push(Body::new("a"));
+    }
+}
@@ -0,0 +1,475 @@
+use nalgebra as na;
+
+use crate::simulation::gravity::octree::Octree;
+use crate::simulation::{Body, Kind, Real, Vector, World};
+
+// What happens when two bodies touch. Only bodies with a nonzero radius collide, and two pinned
+// bodies never do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response {
+    // Perfectly inelastic: the two become one body with their total mass and momentum at their
+    // centre of mass, and the volume of both. It keeps the name of the more massive one.
+    Merge,
+    // Perfectly elastic, as hard spheres. The bodies are also pushed apart until they just touch.
+    Bounce,
+    // Both bodies are taken out of the world
+    Remove,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // The other body was absorbed into `survivor` and no longer exists
+    Merged { survivor: String },
+    Bounced,
+    Removed,
+}
+
+// Emitted for every collision resolved
+#[derive(Clone, Debug, PartialEq)]
+pub struct Collision {
+    pub time: f64,
+    // Names of the two bodies when they met
+    pub bodies: [String; 2],
+    // Point of contact
+    pub position: Vector,
+    // Speed at which the surfaces were closing
+    pub relative_speed: Real,
+    pub outcome: Outcome,
+}
+
+// How pairs that might touch are found before the exact test. Every method finds the same
+// collisions; they differ only in speed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BroadPhase {
+    // Test every pair. Fine for a few dozen bodies.
+    AllPairs,
+    // Sort the boxes swept out by bodies over the step along the axis they are most spread over,
+    // and only test pairs whose boxes overlap
+    SweepAndPrune,
+    // Walk the gravity solver's octree, if it keeps one (see `GravitySolver::octree`), and fall
+    // back to sweep and prune otherwise. The tree's structure is reused as is; its boxes are
+    // recomputed around the swept bodies, so a tree from earlier in the step works too.
+    SolverTree,
+}
+
+// Where every body started the step and how far it moved, with the box its sphere swept out
+struct Swept {
+    start: Vec<Vector>,
+    displacement: Vec<Vector>,
+    min: Vec<Vector>,
+    max: Vec<Vector>,
+}
+
+impl Swept {
+    // Bodies without a start position, e.g. those added since, are taken to have been still
+    fn new(world: &World, previous: &[Vector]) -> Self {
+        let bodies = &world.bodies;
+        let n = bodies.len();
+        let end = world.positions();
+        let start: Vec<Vector> = (0..n)
+            .map(|i| if previous.len() == n { previous[i] } else { end[i] })
+            .collect();
+        // The short way round a periodic box
+        let displacement: Vec<Vector> = (0..n).map(|i| world.separation(&start[i], &end[i])).collect();
+
+        let reach = |i: usize| Vector::repeat(bodies.radius[i]);
+        let min = (0..n).map(|i| (end[i] - displacement[i]).inf(&end[i]) - reach(i)).collect();
+        let max = (0..n).map(|i| (end[i] - displacement[i]).sup(&end[i]) + reach(i)).collect();
+        Self { start, displacement, min, max }
+    }
+
+    fn overlap(&self, i: usize, j: usize) -> bool {
+        (0..3).all(|k| self.min[i][k] <= self.max[j][k] && self.min[j][k] <= self.max[i][k])
+    }
+}
+
+// Whether bodies `i` and `j` can collide at all
+fn collidable(world: &World, i: usize, j: usize) -> bool {
+    let bodies = &world.bodies;
+    bodies.radius[i] > 0.0 && bodies.radius[j] > 0.0
+        && !(bodies.kind[i].is_pinned() && bodies.kind[j].is_pinned())
+}
+
+// Fraction of the step at which the spheres of `i` and `j` first touch, moving in straight lines
+// from their start to their end positions. Checking the whole path rather than the end only
+// means fast bodies can't pass through each other within a step. Pairs which only overlap while
+// moving apart don't count, so a bounce isn't undone on the next step.
+fn contact(world: &World, swept: &Swept, i: usize, j: usize) -> Option<Real> {
+    let bodies = &world.bodies;
+    let reach = bodies.radius[i] + bodies.radius[j];
+    // Relative position d(t) = d0 + t*e over the step
+    let d0 = world.separation(&swept.start[i], &swept.start[j]);
+    let e = swept.displacement[j] - swept.displacement[i];
+
+    let c = d0.magnitude_squared() - reach*reach;
+    if c < 0.0 {
+        // Already touching at the start
+        return if d0.dot(&e) < 0.0 { Some(0.0) } else { None };
+    }
+    let a = e.magnitude_squared();
+    let b = 2.0*d0.dot(&e);
+    let discriminant = b*b - 4.0*a*c;
+    if a == 0.0 || b >= 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    // Earlier root; b < 0 so it is positive
+    let t = (-b - discriminant.sqrt())/(2.0*a);
+    if t <= 1.0 { Some(t) } else { None }
+}
+
+// Pairs (i, j) with i < j which might touch during the step
+fn candidates(world: &World, swept: &Swept, broad_phase: BroadPhase) -> Vec<(usize, usize)> {
+    let n = world.bodies.len();
+    // Swept boxes don't wrap round a periodic box
+    let broad_phase = if world.periodic_box.is_some() { BroadPhase::AllPairs } else { broad_phase };
+
+    let mut pairs = vec![];
+    match broad_phase {
+        BroadPhase::AllPairs => {
+            for i in 0..n {
+                for j in i + 1..n {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        BroadPhase::SweepAndPrune => pairs = sweep_and_prune(world, swept),
+        BroadPhase::SolverTree => match world.solver.octree() {
+            Some(tree) if tree.order.len() == n => pairs = tree_pairs(world, swept, tree),
+            _ => pairs = sweep_and_prune(world, swept),
+        },
+    }
+    pairs.retain(|(i, j)| collidable(world, *i, *j));
+    pairs
+}
+
+fn sweep_and_prune(world: &World, swept: &Swept) -> Vec<(usize, usize)> {
+    let n = world.bodies.len();
+    let colliding: Vec<usize> = (0..n).filter(|i| world.bodies.radius[*i] > 0.0).collect();
+    if colliding.is_empty() {
+        return vec![];
+    }
+
+    // Axis along which the boxes are most spread out, so the fewest overlap on it
+    let centre = |i: usize| 0.5*(swept.min[i] + swept.max[i]);
+    let mean: Vector = colliding.iter().map(|i| centre(*i)).sum::<Vector>()/colliding.len() as Real;
+    let spread: Vector = colliding.iter()
+        .map(|i| (centre(*i) - mean).component_mul(&(centre(*i) - mean)))
+        .sum();
+    let axis = spread.imax();
+
+    let mut order = colliding;
+    order.sort_by(|a, b| swept.min[*a][axis].total_cmp(&swept.min[*b][axis]));
+
+    let mut pairs = vec![];
+    for (k, i) in order.iter().enumerate() {
+        for j in &order[k + 1..] {
+            if swept.min[*j][axis] > swept.max[*i][axis] {
+                break;
+            }
+            if swept.overlap(*i, *j) {
+                pairs.push((*i.min(j), *i.max(j)));
+            }
+        }
+    }
+    pairs
+}
+
+fn tree_pairs(world: &World, swept: &Swept, tree: &Octree) -> Vec<(usize, usize)> {
+    if tree.nodes.is_empty() {
+        return vec![];
+    }
+
+    // Box around everything each node's colliding bodies swept out, children first
+    let mut min = vec![Vector::repeat(Real::INFINITY); tree.nodes.len()];
+    let mut max = vec![Vector::repeat(-Real::INFINITY); tree.nodes.len()];
+    fn fit(node: usize, tree: &Octree, world: &World, swept: &Swept, min: &mut [Vector], max: &mut [Vector]) {
+        let n = &tree.nodes[node];
+        if n.is_leaf() {
+            for i in tree.order[n.start..n.end].iter().filter(|i| world.bodies.radius[**i] > 0.0) {
+                min[node] = min[node].inf(&swept.min[*i]);
+                max[node] = max[node].sup(&swept.max[*i]);
+            }
+            return;
+        }
+        for child in n.children() {
+            fit(child, tree, world, swept, min, max);
+            min[node] = min[node].inf(&min[child]);
+            max[node] = max[node].sup(&max[child]);
+        }
+    }
+    fit(0, tree, world, swept, &mut min, &mut max);
+
+    let mut pairs = vec![];
+    let mut stack = vec![];
+    for i in (0..world.bodies.len()).filter(|i| world.bodies.radius[*i] > 0.0) {
+        stack.push(0);
+        while let Some(node) = stack.pop() {
+            let overlaps = (0..3).all(|k| swept.min[i][k] <= max[node][k] && min[node][k] <= swept.max[i][k]);
+            if !overlaps {
+                continue;
+            }
+            let n = &tree.nodes[node];
+            if n.is_leaf() {
+                for j in &tree.order[n.start..n.end] {
+                    if *j > i && world.bodies.radius[*j] > 0.0 && swept.overlap(i, *j) {
+                        pairs.push((i, *j));
+                    }
+                }
+            } else {
+                stack.extend(n.children());
+            }
+        }
+    }
+    pairs
+}
+
+// Resolve every collision during the step just taken, earliest first. `previous` holds the
+// positions at the start of the step, which was `delta` long and started at `time`. A body takes
+// part in at most one collision per step; any others it was in are found again next step if they
+// still apply.
+pub fn resolve(
+    world: &mut World,
+    response: Response,
+    broad_phase: BroadPhase,
+    previous: &[Vector],
+    time: f64,
+    delta: Real,
+) -> Vec<Collision> {
+    let swept = Swept::new(world, previous);
+    let mut contacts: Vec<(Real, usize, usize)> = candidates(world, &swept, broad_phase).into_iter()
+        .filter_map(|(i, j)| contact(world, &swept, i, j).map(|t| (t, i, j)))
+        .collect();
+    // Each broad phase finds pairs in its own order, so ties are broken by index for them all to
+    // resolve the same collisions
+    contacts.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut involved = vec![false; world.bodies.len()];
+    let mut removed = vec![];
+    let mut collisions = vec![];
+
+    for (t, i, j) in contacts {
+        if involved[i] || involved[j] {
+            continue;
+        }
+        involved[i] = true;
+        involved[j] = true;
+
+        let bodies = &world.bodies;
+        let (first, second) = (bodies.get(i), bodies.get(j));
+        // Where they were when they touched
+        let at_contact = |i: usize| swept.start[i] + t*swept.displacement[i];
+        let dx = world.separation(&at_contact(i), &at_contact(j));
+        let normal = dx.try_normalize(0.0).unwrap_or(Vector::x());
+        let relative_speed = -(second.velocity - first.velocity).dot(&normal);
+        let position = at_contact(i) + normal*first.radius;
+
+        let outcome = match response {
+            Response::Merge => {
+                // The pinned body, or else the more massive one, absorbs the other
+                let keep_first = match (&first.kind, &second.kind) {
+                    (Kind::Pinned(_), _) => true,
+                    (_, Kind::Pinned(_)) => false,
+                    _ => first.mass >= second.mass,
+                };
+                let (survivor, absorbed) = if keep_first { (i, j) } else { (j, i) };
+                let merged = merge(world, &world.bodies.get(survivor), &world.bodies.get(absorbed));
+                world.bodies.set(survivor, merged);
+                removed.push(absorbed);
+                Outcome::Merged { survivor: world.bodies.names[survivor].clone() }
+            }
+            Response::Bounce => {
+                bounce(world, i, j, normal, relative_speed, (1.0 - t)*delta);
+                Outcome::Bounced
+            }
+            Response::Remove => {
+                removed.push(i);
+                removed.push(j);
+                Outcome::Removed
+            }
+        };
+
+        collisions.push(Collision {
+            time: time + na::convert::<Real, f64>(t*delta),
+            bodies: [first.name, second.name],
+            position,
+            relative_speed,
+            outcome,
+        });
+    }
+
+    // From the back, so earlier indices stay valid
+    removed.sort_unstable();
+    for i in removed.into_iter().rev() {
+        world.bodies.remove(i);
+    }
+    collisions
+}
+
+// `survivor` after swallowing `absorbed`. A pinned survivor stays on its course.
+fn merge(world: &World, survivor: &Body, absorbed: &Body) -> Body {
+    let mut merged = survivor.clone();
+    merged.mass = survivor.mass + absorbed.mass;
+    merged.radius = (survivor.radius.powi(3) + absorbed.radius.powi(3)).cbrt();
+    if survivor.kind.is_pinned() || merged.mass == 0.0 {
+        return merged;
+    }
+
+    // Offset taken the short way round a periodic box
+    let offset = world.separation(&survivor.position, &absorbed.position);
+    let share = absorbed.mass/merged.mass;
+    merged.position = survivor.position + share*offset;
+    merged.velocity = survivor.velocity + share*(absorbed.velocity - survivor.velocity);
+    merged.acceleration = survivor.acceleration + share*(absorbed.acceleration - survivor.acceleration);
+    merged
+}
+
+// Exchange momentum along `normal` as two hard spheres would at contact, `remaining` before the
+// end of the step, and move them as if they had bounced then. If they still overlap they are
+// separated along `normal` until they just touch. Pinned bodies act as if infinitely massive,
+// test particles as if massless.
+fn bounce(world: &mut World, i: usize, j: usize, normal: Vector, closing_speed: Real, remaining: Real) {
+    let bodies = &mut world.bodies;
+    // Share of the velocity change and separation taken by each body
+    let (share_i, share_j) = match (&bodies.kind[i], &bodies.kind[j]) {
+        (Kind::Pinned(_), _) => (0.0, 1.0),
+        (_, Kind::Pinned(_)) => (1.0, 0.0),
+        _ => {
+            let total = bodies.mass[i] + bodies.mass[j];
+            if total == 0.0 {
+                (0.5, 0.5)
+            } else {
+                (bodies.mass[j]/total, bodies.mass[i]/total)
+            }
+        }
+    };
+
+    let change = 2.0*closing_speed*normal;
+    bodies.set_velocity(i, bodies.velocity(i) - share_i*change);
+    bodies.set_velocity(j, bodies.velocity(j) + share_j*change);
+    bodies.set_position(i, bodies.position(i) - share_i*remaining*change);
+    bodies.set_position(j, bodies.position(j) + share_j*remaining*change);
+
+    let dx = world.separation(&world.bodies.position(i), &world.bodies.position(j));
+    let overlap = world.bodies.radius[i] + world.bodies.radius[j] - dx.dot(&normal);
+    if overlap > 0.0 {
+        let bodies = &mut world.bodies;
+        bodies.set_position(i, bodies.position(i) - share_i*overlap*normal);
+        bodies.set_position(j, bodies.position(j) + share_j*overlap*normal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::gravity::barnes_hut::BarnesHut;
+    use crate::simulation::tests::cluster;
+
+    // Masses 1 and 3 of radius 0.1, which pass right through each other over a step of 1 if
+    // nothing stops them. Returns the world at the end of the step and the positions at its start.
+    fn crossing() -> (World, Vec<Vector>) {
+        let mut world = World::new();
+        let start = vec![Vector::new(-1.0, 0.0, 0.0), Vector::new(1.0, 0.05, 0.0)];
+        let velocities = [Vector::new(2.0, 0.0, 0.0), Vector::new(-1.0, -0.1, 0.0)];
+        for (k, mass) in [1.0, 3.0].into_iter().enumerate() {
+            let mut body = Body::new(["light", "heavy"][k]);
+            body.mass = mass;
+            body.radius = 0.1;
+            body.position = start[k] + velocities[k];
+            body.velocity = velocities[k];
+            world.add_body(body);
+        }
+        (world, start)
+    }
+
+    fn momentum(world: &World) -> Vector {
+        (0..world.bodies.len()).map(|i| world.bodies.mass[i]*world.bodies.velocity(i)).sum()
+    }
+
+    fn kinetic(world: &World) -> Real {
+        (0..world.bodies.len()).map(|i| 0.5*world.bodies.mass[i]*world.bodies.velocity(i).magnitude_squared()).sum()
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let (mut world, start) = crossing();
+        let (before, centre) = (momentum(&world), world.barycentre().unwrap().0);
+        let collisions = resolve(&mut world, Response::Merge, BroadPhase::AllPairs, &start, 2.0, 1.0);
+
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].outcome, Outcome::Merged { survivor: "heavy".to_string() });
+        assert!(collisions[0].time > 2.0 && collisions[0].time < 3.0);
+        assert_eq!(world.bodies.len(), 1);
+        assert_eq!(world.bodies.mass[0], 4.0);
+        assert!((momentum(&world) - before).magnitude() < 1e2*Real::EPSILON);
+        assert!((world.bodies.position(0) - centre).magnitude() < 1e2*Real::EPSILON);
+        // Volume is kept too
+        assert!((world.bodies.radius[0] - 0.1*(2.0 as Real).cbrt()).abs() < 1e2*Real::EPSILON);
+    }
+
+    #[test]
+    fn bounce_conserves_momentum_and_energy() {
+        let (mut world, start) = crossing();
+        let (momentum_before, kinetic_before) = (momentum(&world), kinetic(&world));
+        let collisions = resolve(&mut world, Response::Bounce, BroadPhase::AllPairs, &start, 0.0, 1.0);
+
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].relative_speed > 0.0);
+        assert_eq!(world.bodies.len(), 2);
+        assert!((momentum(&world) - momentum_before).magnitude() < 1e2*Real::EPSILON);
+        assert!((kinetic(&world) - kinetic_before).abs() < 1e2*Real::EPSILON);
+        // Moving apart without overlapping, and not again next step
+        let dx = world.bodies.position(1) - world.bodies.position(0);
+        assert!(dx.magnitude() >= 0.2*(1.0 - 1e2*Real::EPSILON));
+        assert!(dx.dot(&(world.bodies.velocity(1) - world.bodies.velocity(0))) > 0.0);
+        let start = world.positions();
+        for (i, position) in start.iter().enumerate() {
+            world.bodies.set_position(i, position + world.bodies.velocity(i));
+        }
+        assert!(resolve(&mut world, Response::Bounce, BroadPhase::AllPairs, &start, 1.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn remove_both() {
+        let (mut world, start) = crossing();
+        let collisions = resolve(&mut world, Response::Remove, BroadPhase::AllPairs, &start, 0.0, 1.0);
+        assert_eq!(collisions[0].bodies, ["light".to_string(), "heavy".to_string()]);
+        assert_eq!(world.bodies.len(), 0);
+    }
+
+    #[test]
+    fn no_collision_without_radius() {
+        let (mut world, start) = crossing();
+        world.bodies.radius[0] = 0.0;
+        assert!(resolve(&mut world, Response::Merge, BroadPhase::AllPairs, &start, 0.0, 1.0).is_empty());
+        assert_eq!(world.bodies.len(), 2);
+    }
+
+    #[test]
+    fn broad_phases_agree() {
+        // Crowded enough that many pairs already touch at the start, which ties their times
+        let resolved = |broad_phase: BroadPhase| {
+            let mut world = World::new();
+            world.solver = Box::new(BarnesHut::new(0.5));
+            let bodies = cluster(500, 3);
+            for i in 0..bodies.len() {
+                let mut body = bodies.get(i);
+                body.radius = 0.02 + 0.01*(i % 3) as Real;
+                body.velocity = Vector::new((i as Real).sin(), (2.0*i as Real).cos(), (3.0*i as Real).sin());
+                world.add_body(body);
+            }
+            world.nbody();
+            let start = world.positions();
+            for (i, position) in start.iter().enumerate() {
+                world.bodies.set_position(i, position + 0.05*world.bodies.velocity(i));
+            }
+            let collisions = resolve(&mut world, Response::Merge, broad_phase, &start, 0.0, 0.05);
+            (collisions, world.positions())
+        };
+
+        let all_pairs = resolved(BroadPhase::AllPairs);
+        assert!(all_pairs.0.len() > 20, "{} collisions", all_pairs.0.len());
+        assert!(all_pairs.0.iter().filter(|collision| collision.time == 0.0).count() > 1);
+        assert!(resolved(BroadPhase::SweepAndPrune) == all_pairs);
+        assert!(resolved(BroadPhase::SolverTree) == all_pairs);
+    }
+}
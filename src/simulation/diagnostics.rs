@@ -0,0 +1,369 @@
+use nalgebra as na;
+
+use std::collections::VecDeque;
+use std::io::Write;
+
+use crate::simulation::{parallel, World};
+
+// Quantities are summed in f64 whatever `Real` is, so the drift measured is the integrator's and
+// not the summation's
+type Real64 = f64;
+type Vector64 = na::Vector3<Real64>;
+
+// Totals of the quantities an isolated system conserves, measured at one instant. Potential
+// energy is the softened pairwise sum over every pair, using minimum image separations in a
+// periodic box, unless the solver has potentials of its own (see `GravitySolver::potentials`);
+// massless bodies contribute nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conserved {
+    pub time: f64,
+    pub step: u64,
+
+    pub kinetic: f64,
+    pub potential: f64,
+    pub momentum: Vector64,
+    // About the world origin
+    pub angular_momentum: Vector64,
+    pub centre_of_mass: Vector64,
+    pub centre_of_mass_velocity: Vector64,
+    pub mass: f64,
+
+    // Sums of m|v| and m|r x v|. Scales for momentum and angular momentum errors, which stay
+    // meaningful when the totals themselves are zero.
+    momentum_scale: f64,
+    angular_momentum_scale: f64,
+}
+
+impl Conserved {
+    pub fn measure(world: &World, time: f64, step: u64) -> Self {
+        let bodies = &world.bodies;
+
+        let mut measured = Self {
+            time,
+            step,
+            kinetic: 0.0,
+            potential: 0.0,
+            momentum: Vector64::zeros(),
+            angular_momentum: Vector64::zeros(),
+            centre_of_mass: Vector64::zeros(),
+            centre_of_mass_velocity: Vector64::zeros(),
+            mass: 0.0,
+            momentum_scale: 0.0,
+            angular_momentum_scale: 0.0,
+        };
+
+        for i in 0..bodies.len() {
+            let mass = bodies.mass[i] as Real64;
+            let position = bodies.position(i).cast::<Real64>();
+            let velocity = bodies.velocity(i).cast::<Real64>();
+            let angular_momentum = mass*position.cross(&velocity);
+
+            measured.kinetic += 0.5*mass*velocity.magnitude_squared();
+            measured.momentum += mass*velocity;
+            measured.angular_momentum += angular_momentum;
+            measured.centre_of_mass += mass*position;
+            measured.mass += mass;
+            measured.momentum_scale += mass*velocity.magnitude();
+            measured.angular_momentum_scale += angular_momentum.magnitude();
+        }
+        if measured.mass != 0.0 {
+            measured.centre_of_mass /= measured.mass;
+            measured.centre_of_mass_velocity = measured.momentum/measured.mass;
+        }
+
+        // Periodic solvers such as the particle mesh pull with more than the nearest image
+        let (solver, gravity) = (&world.solver, &world.gravity);
+        if let Some(potentials) = world.pool.install(|| solver.potentials(bodies, gravity)) {
+            measured.potential = 0.5*potentials.iter().zip(&bodies.mass)
+                .map(|(potential, mass)| *potential as Real64*(*mass as Real64))
+                .sum::<Real64>();
+            return measured;
+        }
+
+        // Each pair once, from the body with the lower index
+        let periodic_box = world.periodic_box;
+        let potentials = world.pool.install(|| parallel::map(bodies.len(), |i| {
+            let mut potential = 0.0;
+            if bodies.mass[i] == 0.0 {
+                return potential;
+            }
+            let position = bodies.position(i);
+            for j in i + 1..bodies.len() {
+                if bodies.mass[j] != 0.0 {
+                    let dx = bodies.position(j) - position;
+                    let dx = match periodic_box {
+                        Some(size) => dx.map(|x| x - size*(x/size).round()),
+                        None => dx,
+                    };
+                    let epsilon = gravity.pair_softening(bodies.softening[i], bodies.softening[j]);
+                    potential += gravity.potential(dx, bodies.mass[i], bodies.mass[j], epsilon) as Real64;
+                }
+            }
+            potential
+        }));
+        measured.potential = potentials.iter().sum();
+
+        measured
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+}
+
+// Error in the conserved quantities since the first measurement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Drift {
+    // (E - E0)/|E0|
+    pub energy: f64,
+    // |P - P0| over the initial sum of m|v|
+    pub momentum: f64,
+    // |L - L0| over the initial sum of m|r x v|
+    pub angular_momentum: f64,
+    // Distance of the centre of mass from where uniform motion from t=0 would have put it
+    pub centre_of_mass: f64,
+}
+
+impl Drift {
+    pub fn between(initial: &Conserved, latest: &Conserved) -> Self {
+        // Falls back to the absolute error if there is nothing to compare against
+        let relative = |error: f64, scale: f64| if scale == 0.0 { error } else { error/scale };
+
+        let expected_centre = initial.centre_of_mass
+            + (latest.time - initial.time)*initial.centre_of_mass_velocity;
+        Self {
+            energy: relative(latest.energy() - initial.energy(), initial.energy().abs()),
+            momentum: relative((latest.momentum - initial.momentum).magnitude(), initial.momentum_scale),
+            angular_momentum: relative(
+                (latest.angular_momentum - initial.angular_momentum).magnitude(),
+                initial.angular_momentum_scale,
+            ),
+            centre_of_mass: (latest.centre_of_mass - expected_centre).magnitude(),
+        }
+    }
+}
+
+// Measures the conserved quantities every `interval` steps of a `Simulation`, keeping recent
+// measurements for plotting and optionally writing each one to a log file. The log is buffered
+// and flushed when the diagnostics are dropped; if writing fails it is closed with a warning and
+// the run carries on.
+pub struct Diagnostics {
+    // Steps between measurements. 0 turns diagnostics off.
+    pub interval: u64,
+    // Most measurements kept in `history`. Drift is always relative to the first one, which is
+    // kept separately.
+    pub history_length: usize,
+
+    initial: Option<Conserved>,
+    history: VecDeque<Conserved>,
+    log: Option<std::io::BufWriter<std::fs::File>>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self {
+            interval: 0,
+            history_length: 1000,
+            initial: None,
+            history: VecDeque::new(),
+            log: None,
+        }
+    }
+
+    // Also write every measurement from now on to the file at `path`, one line each
+    pub fn log_to(&mut self, path: &str) -> std::io::Result<()> {
+        let mut log = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(
+            log,
+            "# time step kinetic potential energy energy_drift momentum_drift angular_momentum_drift centre_of_mass_drift"
+        )?;
+        self.log = Some(log);
+        Ok(())
+    }
+
+    // Measure the world now. The first measurement is the reference for drift.
+    pub fn record(&mut self, world: &World, time: f64, step: u64) {
+        let measured = Conserved::measure(world, time, step);
+        let initial = *self.initial.get_or_insert(measured);
+
+        if let Some(log) = &mut self.log {
+            let drift = Drift::between(&initial, &measured);
+            let written = writeln!(
+                log,
+                "{} {} {:e} {:e} {:e} {:e} {:e} {:e} {:e}",
+                measured.time,
+                measured.step,
+                measured.kinetic,
+                measured.potential,
+                measured.energy(),
+                drift.energy,
+                drift.momentum,
+                drift.angular_momentum,
+                drift.centre_of_mass,
+            );
+            if let Err(error) = written {
+                self.close_log(error);
+            }
+        }
+
+        self.history.push_back(measured);
+        while self.history.len() > self.history_length {
+            self.history.pop_front();
+        }
+    }
+
+    // Stop logging after a failed write. Whatever is still buffered is lost with it.
+    fn close_log(&mut self, error: std::io::Error) {
+        eprintln!("diagnostics log closed: {}", error);
+        if let Some(log) = self.log.take() {
+            let _ = log.into_parts();
+        }
+    }
+
+    // Forget every measurement, so the next one becomes the new reference. Needed after changing
+    // the world by hand, e.g. adding bodies.
+    pub fn reset(&mut self) {
+        self.initial = None;
+        self.history.clear();
+    }
+
+    pub fn initial(&self) -> Option<&Conserved> {
+        self.initial.as_ref()
+    }
+
+    pub fn latest(&self) -> Option<&Conserved> {
+        self.history.back()
+    }
+
+    // Oldest first
+    pub fn history(&self) -> &VecDeque<Conserved> {
+        &self.history
+    }
+
+    // Drift of the latest measurement
+    pub fn drift(&self) -> Option<Drift> {
+        Some(Drift::between(self.initial()?, self.latest()?))
+    }
+
+    // Drift of every measurement in `history`
+    pub fn drift_history(&self) -> Vec<Drift> {
+        match &self.initial {
+            Some(initial) => self.history.iter().map(|measured| Drift::between(initial, measured)).collect(),
+            None => vec![],
+        }
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Diagnostics {
+    fn drop(&mut self) {
+        if let Some(Err(error)) = self.log.as_mut().map(|log| log.flush()) {
+            self.close_log(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::integrator::Leapfrog;
+    use crate::simulation::tests::{period, two_body};
+    use crate::simulation::gravity::particle_mesh::ParticleMesh;
+    use crate::simulation::{Body, Real, Simulation, Vector};
+
+    #[test]
+    fn kepler_orbit() {
+        // Energy -G*m1*m2/2a and angular momentum mu*sqrt(G*M*a*(1 - e^2)) in the barycentric frame
+        let (mass, eccentricity): (Real, Real) = (1e-3, 0.5);
+        let measured = Conserved::measure(&two_body(mass, eccentricity), 0.0, 0);
+        let (mass, eccentricity) = (mass as Real64, eccentricity as Real64);
+        let reduced = mass/(1.0 + mass);
+        let angular_momentum = reduced*((1.0 + mass)*(1.0 - eccentricity*eccentricity)).sqrt();
+
+        let tolerance = 1e2*Real::EPSILON as Real64;
+        assert!((measured.energy() + mass/2.0).abs() < tolerance*mass, "energy {}", measured.energy());
+        assert!((measured.angular_momentum.magnitude() - angular_momentum).abs() < tolerance*angular_momentum);
+        assert!(measured.momentum.magnitude() < tolerance*mass);
+        assert!(measured.centre_of_mass.magnitude() < tolerance);
+        assert_eq!(measured.mass, 1.0 + mass);
+    }
+
+    #[test]
+    fn minimum_image_potential() {
+        let mut world = World::new();
+        world.periodic_box = Some(10.0);
+        for x in [0.5, 9.5] {
+            let mut body = Body::new(&x.to_string());
+            body.mass = 1.0;
+            body.position.x = x;
+            world.add_body(body);
+        }
+        // One apart across the boundary, not nine
+        assert_eq!(Conserved::measure(&world, 0.0, 0).potential, -1.0);
+    }
+
+    #[test]
+    fn periodic_potential_from_the_solver() {
+        let mut world = World::new();
+        world.periodic_box = Some(10.0);
+        world.solver = Box::new(ParticleMesh::new(16, 10.0));
+        for x in [0.5, 9.5, 4.0] {
+            let mut body = Body::new(&x.to_string());
+            body.position = Vector::new(x, 5.0, 5.0);
+            world.add_body(body);
+        }
+
+        let potentials = world.solver.potentials(&world.bodies, &world.gravity).unwrap();
+        let expected = 0.5*potentials.iter().map(|potential| *potential as Real64).sum::<Real64>();
+        assert_eq!(Conserved::measure(&world, 0.0, 0).potential, expected);
+    }
+
+    // Writing to /dev/full always fails once the buffer spills
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn failed_log_writes_close_the_log() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.log_to("/dev/full").unwrap();
+        let world = two_body(1e-3, 0.5);
+        for step in 0..1000 {
+            diagnostics.record(&world, step as f64, step);
+        }
+        assert!(diagnostics.log.is_none());
+        assert_eq!(diagnostics.latest().unwrap().step, 999);
+    }
+
+    #[test]
+    fn drift_over_ten_orbits() {
+        let mut sim = Simulation::new();
+        sim.world = two_body(1e-3, 0.5);
+        sim.set_integrator(Box::new(Leapfrog::new()));
+        sim.diagnostics.interval = 10;
+        sim.diagnostics.history_length = 5;
+        let delta = (period(1e-3)/1000.0) as Real64;
+        for _ in 0..10000 {
+            sim.step(delta);
+        }
+
+        assert_eq!(sim.diagnostics.initial().unwrap().step, 0);
+        assert_eq!(sim.diagnostics.latest().unwrap().step, 10000);
+        assert_eq!(sim.diagnostics.history().len(), 5);
+        assert_eq!(sim.diagnostics.drift_history().len(), 5);
+
+        // Leapfrog keeps the energy error bounded and conserves momentum and angular momentum up
+        // to round-off
+        let drift = sim.diagnostics.drift().unwrap();
+        let round_off = 1e4*Real::EPSILON as Real64;
+        assert!(drift.energy.abs() < 1e-4, "energy drift {:e}", drift.energy);
+        assert!(drift.momentum < round_off, "momentum drift {:e}", drift.momentum);
+        assert!(drift.angular_momentum < round_off, "angular momentum drift {:e}", drift.angular_momentum);
+        assert!(drift.centre_of_mass < round_off, "centre of mass drift {:e}", drift.centre_of_mass);
+
+        sim.diagnostics.reset();
+        assert!(sim.diagnostics.drift().is_none());
+    }
+}
@@ -0,0 +1,195 @@
+use nalgebra as na;
+
+use crate::simulation::{Real, Vector};
+
+// Keplerian orbital elements of a two-body orbit. Angles are in radians, measured in the world's
+// x-y plane with the ascending node counted from the x axis. Hyperbolic orbits have a negative
+// semi-major axis; exactly parabolic orbits can't be represented.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Elements {
+    pub semi_major_axis: Real,
+    pub eccentricity: Real,
+    pub inclination: Real,
+    pub longitude_of_ascending_node: Real,
+    pub argument_of_periapsis: Real,
+    pub true_anomaly: Real,
+}
+
+// Below this eccentricity or inclination the periapsis or node is undefined and taken from the
+// reference direction instead. Kept well above round-off, which would otherwise make up a
+// periapsis for circular orbits in single precision.
+const DEGENERATE: Real = 1e4*Real::EPSILON;
+
+const TAU: Real = std::f64::consts::TAU as Real;
+
+impl Elements {
+    // Relative position and velocity, with mu = G*(m1 + m2)
+    pub fn to_state(self, mu: Real) -> (Vector, Vector) {
+        let e = self.eccentricity;
+        let p = self.semi_major_axis*(1.0 - e*e);
+        let (sin, cos) = self.true_anomaly.sin_cos();
+        let r = p/(1.0 + e*cos);
+
+        // In the orbital plane, periapsis along x
+        let position = Vector::new(r*cos, r*sin, 0.0);
+        let velocity = (mu/p).sqrt()*Vector::new(-sin, e + cos, 0.0);
+
+        let rotation = na::Rotation3::from_axis_angle(&Vector::z_axis(), self.longitude_of_ascending_node)
+            *na::Rotation3::from_axis_angle(&Vector::x_axis(), self.inclination)
+            *na::Rotation3::from_axis_angle(&Vector::z_axis(), self.argument_of_periapsis);
+        (rotation*position, rotation*velocity)
+    }
+
+    // Osculating elements of a relative position and velocity, with mu = G*(m1 + m2)
+    pub fn from_state(position: Vector, velocity: Vector, mu: Real) -> Self {
+        let r = position.magnitude();
+        let angular_momentum = position.cross(&velocity);
+        let normal = angular_momentum.normalize();
+        let eccentricity_vector = ((velocity.magnitude_squared() - mu/r)*position
+            - position.dot(&velocity)*velocity)/mu;
+        let e = eccentricity_vector.magnitude();
+        let energy = 0.5*velocity.magnitude_squared() - mu/r;
+
+        // Line of nodes, or the x axis for orbits in the reference plane
+        let node = Vector::z().cross(&angular_momentum);
+        let node = if node.magnitude() > DEGENERATE*angular_momentum.magnitude() {
+            node.normalize()
+        } else {
+            Vector::x()
+        };
+        // Periapsis, or the node for circular orbits
+        let periapsis = if e > DEGENERATE { eccentricity_vector/e } else { node };
+        // Signed angle from `from` to `to` about the orbit normal
+        let angle = |from: &Vector, to: &Vector| normal.dot(&from.cross(to)).atan2(from.dot(to));
+
+        Self {
+            semi_major_axis: -mu/(2.0*energy),
+            eccentricity: e,
+            inclination: (angular_momentum.z/angular_momentum.magnitude()).clamp(-1.0, 1.0).acos(),
+            longitude_of_ascending_node: node.y.atan2(node.x).rem_euclid(TAU),
+            argument_of_periapsis: angle(&node, &periapsis).rem_euclid(TAU),
+            true_anomaly: angle(&periapsis, &position).rem_euclid(TAU),
+        }
+    }
+
+    // Mean anomaly from the true anomaly. Grows linearly with time: by 2*pi per orbit for
+    // ellipses, and as the hyperbolic mean anomaly for hyperbolas.
+    pub fn mean_anomaly(&self) -> Real {
+        let e = self.eccentricity;
+        let (sin, cos) = self.true_anomaly.sin_cos();
+        if e < 1.0 {
+            let eccentric = ((1.0 - e*e).sqrt()*sin).atan2(e + cos);
+            (eccentric - e*eccentric.sin()).rem_euclid(TAU)
+        } else {
+            let hyperbolic = 2.0*(((e - 1.0)/(e + 1.0)).sqrt()*(0.5*self.true_anomaly).tan()).atanh();
+            e*hyperbolic.sinh() - hyperbolic
+        }
+    }
+
+    // Set the true anomaly from a mean anomaly by solving Kepler's equation
+    pub fn set_mean_anomaly(&mut self, mean: Real) {
+        let e = self.eccentricity;
+        self.true_anomaly = if e < 1.0 {
+            // E - e*sin E = M, starting from E = pi for high eccentricities
+            let mean = mean.rem_euclid(TAU);
+            let mut eccentric = if e > 0.8 { 0.5*TAU } else { mean };
+            for _ in 0..64 {
+                let step = (eccentric - e*eccentric.sin() - mean)/(1.0 - e*eccentric.cos());
+                eccentric -= step;
+                if step.abs() <= Real::EPSILON*TAU {
+                    break;
+                }
+            }
+            (2.0*((1.0 + e).sqrt()*(0.5*eccentric).sin()).atan2((1.0 - e).sqrt()*(0.5*eccentric).cos()))
+                .rem_euclid(TAU)
+        } else {
+            // e*sinh F - F = M
+            let mut hyperbolic = (mean/e).asinh();
+            for _ in 0..64 {
+                let step = (e*hyperbolic.sinh() - hyperbolic - mean)/(e*hyperbolic.cosh() - 1.0);
+                hyperbolic -= step;
+                if step.abs() <= Real::EPSILON*hyperbolic.abs().max(1.0) {
+                    break;
+                }
+            }
+            2.0*(((e + 1.0)/(e - 1.0)).sqrt()*(0.5*hyperbolic).tanh()).atan()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Elements, expected: Elements) {
+        let angle = |a: Real, b: Real| ((a - b + 0.5*TAU).rem_euclid(TAU) - 0.5*TAU).abs();
+        let tolerance = 1e4*Real::EPSILON;
+        assert!(
+            (actual.semi_major_axis/expected.semi_major_axis - 1.0).abs() < tolerance
+                && (actual.eccentricity - expected.eccentricity).abs() < tolerance
+                && angle(actual.inclination, expected.inclination) < tolerance
+                && angle(actual.longitude_of_ascending_node, expected.longitude_of_ascending_node) < tolerance
+                && angle(actual.argument_of_periapsis, expected.argument_of_periapsis) < tolerance
+                && angle(actual.true_anomaly, expected.true_anomaly) < tolerance,
+            "{:?} instead of {:?}", actual, expected,
+        );
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let mu = 2.5;
+        for (semi_major_axis, eccentricity) in [(1.0, 0.1), (3.0, 0.7), (1.0, 0.99), (-2.0, 1.3), (-0.5, 4.0)] {
+            for true_anomaly in [0.1, 1.0, 2.0, 5.5] {
+                let elements = Elements {
+                    semi_major_axis,
+                    eccentricity,
+                    inclination: 0.7,
+                    longitude_of_ascending_node: 4.0,
+                    argument_of_periapsis: 0.5,
+                    true_anomaly,
+                };
+                // Only part of a hyperbola is ever reached
+                if eccentricity > 1.0 && true_anomaly.cos() <= -1.0/eccentricity {
+                    continue;
+                }
+                let (position, velocity) = elements.to_state(mu);
+                assert_close(Elements::from_state(position, velocity, mu), elements);
+            }
+        }
+    }
+
+    #[test]
+    fn circular_and_planar_orbits() {
+        // Periapsis falls back on the node, and the node on the x axis
+        let elements = Elements {
+            semi_major_axis: 2.0,
+            eccentricity: 0.0,
+            inclination: 0.0,
+            longitude_of_ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            true_anomaly: 1.2,
+        };
+        let (position, velocity) = elements.to_state(1.0);
+        assert!((velocity.magnitude() - (0.5 as Real).sqrt()).abs() < 1e2*Real::EPSILON);
+        assert_close(Elements::from_state(position, velocity, 1.0), elements);
+    }
+
+    #[test]
+    fn mean_anomaly_round_trip() {
+        for eccentricity in [0.0, 0.3, 0.9, 0.999, 1.5, 10.0] {
+            for mean in [0.01, 1.0, 3.0, 6.0] {
+                let mut elements = Elements {
+                    semi_major_axis: if eccentricity < 1.0 { 1.0 } else { -1.0 },
+                    eccentricity,
+                    inclination: 0.0,
+                    longitude_of_ascending_node: 0.0,
+                    argument_of_periapsis: 0.0,
+                    true_anomaly: 0.0,
+                };
+                elements.set_mean_anomaly(mean);
+                let error = (elements.mean_anomaly() - mean).abs();
+                assert!(error < 1e4*Real::EPSILON*mean.max(1.0), "e = {} M = {}: {:e}", eccentricity, mean, error);
+            }
+        }
+    }
+}
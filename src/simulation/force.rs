@@ -0,0 +1,64 @@
+pub mod drag;
+pub mod oblateness;
+pub mod radiation_pressure;
+pub mod tides;
+
+use crate::simulation::{gravity, Bodies, Vector};
+
+// An extra force on top of point-mass gravity, added to the accelerations by `World::nbody`
+// whenever they are evaluated. Forces may depend on velocities; the integrators just see them as
+// part of the acceleration. Any number can be pushed onto `World::forces`, and they add up.
+pub trait Force: Send {
+    fn name(&self) -> &'static str;
+
+    // Acceleration of each body in `targets` due to this force, in `targets` order. Pinned
+    // bodies are zeroed afterwards, so they need no special care.
+    fn accelerations(&self, targets: &[usize], bodies: &Bodies, gravity: &gravity::Gravity) -> Vec<Vector>;
+}
+
+// Accelerations of `targets` for a force between body `primary` and each other body, where
+// `pull(j)` is the acceleration of body j. The primary takes the reaction, so momentum is
+// conserved, unless it is massless.
+fn with_reaction(targets: &[usize], bodies: &Bodies, primary: usize, pull: impl Fn(usize) -> Vector) -> Vec<Vector> {
+    targets.iter()
+        .map(|i| {
+            if *i != primary {
+                return pull(*i);
+            }
+            if bodies.mass[primary] == 0.0 {
+                return Vector::zeros();
+            }
+            -(0..bodies.len())
+                .filter(|j| *j != primary && bodies.mass[*j] != 0.0)
+                .map(|j| bodies.mass[j]*pull(j))
+                .sum::<Vector>()/bodies.mass[primary]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::cluster;
+    use crate::simulation::{Body, Kind, Real};
+
+    #[test]
+    fn primary_takes_the_reaction() {
+        let mut bodies = cluster(20, 1);
+        let mut particle = Body::new("particle");
+        particle.kind = Kind::TestParticle;
+        bodies.push(particle);
+        let pull = |j: usize| Vector::new(1.0, j as Real, -(j as Real).sqrt());
+
+        let all: Vec<usize> = (0..bodies.len()).collect();
+        let accelerations = with_reaction(&all, &bodies, 3, pull);
+        let momentum: Vector = all.iter().map(|i| bodies.mass[*i]*accelerations[*i]).sum();
+        assert!(momentum.magnitude() < 1e2*Real::EPSILON, "{:?}", momentum);
+        // Massless bodies still feel the force
+        assert_eq!(accelerations[20], pull(20));
+
+        // Unless the primary is massless too
+        bodies.mass[3] = 0.0;
+        assert_eq!(with_reaction(&[3], &bodies, 3, pull), [Vector::zeros()]);
+    }
+}
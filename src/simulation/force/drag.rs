@@ -0,0 +1,97 @@
+use crate::simulation::force::Force;
+use crate::simulation::{gravity, Bodies, Real, Vector};
+
+// Drag from a uniform medium, e.g. gas in a protoplanetary disc or an atmosphere:
+// a = -(linear + quadratic*|u|)*u, with u the velocity relative to the medium. Coefficients are
+// per unit mass, so the same for every body.
+pub struct Drag {
+    // Inverse of the stopping time, 1/time
+    pub linear: Real,
+    // 1/length
+    pub quadratic: Real,
+    pub medium_velocity: Vector,
+}
+
+impl Drag {
+    pub fn linear(coefficient: Real) -> Self {
+        Self {
+            linear: coefficient,
+            quadratic: 0.0,
+            medium_velocity: Vector::zeros(),
+        }
+    }
+
+    pub fn quadratic(coefficient: Real) -> Self {
+        Self {
+            linear: 0.0,
+            quadratic: coefficient,
+            medium_velocity: Vector::zeros(),
+        }
+    }
+}
+
+impl Force for Drag {
+    fn name(&self) -> &'static str {
+        "drag"
+    }
+
+    fn accelerations(&self, targets: &[usize], bodies: &Bodies, _gravity: &gravity::Gravity) -> Vec<Vector> {
+        targets.iter()
+            .map(|i| {
+                let u = bodies.velocity(*i) - self.medium_velocity;
+                -(self.linear + self.quadratic*u.magnitude())*u
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::integrator::{Integrator, RungeKutta4};
+    use crate::simulation::{Body, World};
+
+    // Round-off sets a floor in single precision
+    const TOLERANCE: Real = if 1e2*Real::EPSILON > 1e-8 { 1e2*Real::EPSILON } else { 1e-8 };
+
+    // Position and velocity of a lone body set moving at unit speed through the medium, after
+    // unit time
+    fn coast(drag: Drag) -> (Real, Real) {
+        let mut world = World::new();
+        let mut body = Body::new("body");
+        body.velocity = Vector::new(1.0, 0.0, 0.0);
+        world.add_body(body);
+        world.forces.push(Box::new(drag));
+        for _ in 0..100 {
+            RungeKutta4.step(&mut world, 0.01);
+        }
+        (world.bodies.x[0], world.bodies.vx[0])
+    }
+
+    #[test]
+    fn linear_drag() {
+        // v = exp(-k*t), x = (1 - exp(-k*t))/k
+        let k = 2.0;
+        let (x, v) = coast(Drag::linear(k));
+        assert!((v - (-k).exp()).abs() < TOLERANCE, "{}", v);
+        assert!((x - (1.0 - (-k).exp())/k).abs() < TOLERANCE, "{}", x);
+    }
+
+    #[test]
+    fn quadratic_drag() {
+        // v = 1/(1 + q*t), x = ln(1 + q*t)/q
+        let q = 2.0;
+        let (x, v) = coast(Drag::quadratic(q));
+        assert!((v - 1.0/(1.0 + q)).abs() < TOLERANCE, "{}", v);
+        assert!((x - (1.0 + q).ln()/q).abs() < TOLERANCE, "{}", x);
+    }
+
+    #[test]
+    fn no_drag_moving_with_medium() {
+        let mut drag = Drag::linear(1.0);
+        drag.medium_velocity = Vector::new(1.0, 0.0, 0.0);
+        let (x, v) = coast(drag);
+        assert_eq!(v, 1.0);
+        assert!((x - 1.0).abs() < TOLERANCE, "{}", x);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::simulation::force::{with_reaction, Force};
+use crate::simulation::{gravity, Bodies, Real, Vector};
+
+// Gravity of the equatorial bulge of a flattened primary, from the J2 term of its potential:
+// a = -3/2*J2*G*M*R^2/r^5*((1 - 5*z^2/r^2)*r + 2*z*pole), with z the height above its equator.
+// Makes orbits about the primary precess. Acts on every body with the primary taking the
+// reaction, except that the pole is held fixed.
+pub struct Oblateness {
+    // Name of the flattened body
+    pub primary: String,
+    pub j2: Real,
+    // Equatorial radius J2 is given for
+    pub radius: Real,
+    // Unit vector along the primary's spin axis
+    pub pole: Vector,
+}
+
+impl Oblateness {
+    // Pole along +z
+    pub fn new(primary: &str, j2: Real, radius: Real) -> Self {
+        Self {
+            primary: primary.to_string(),
+            j2,
+            radius,
+            pole: Vector::z(),
+        }
+    }
+}
+
+impl Force for Oblateness {
+    fn name(&self) -> &'static str {
+        "J2 oblateness"
+    }
+
+    fn accelerations(&self, targets: &[usize], bodies: &Bodies, gravity: &gravity::Gravity) -> Vec<Vector> {
+        let Some(primary) = bodies.index_of(&self.primary) else {
+            return vec![Vector::zeros(); targets.len()];
+        };
+        let factor = -1.5*self.j2*gravity.g*bodies.mass[primary]*self.radius*self.radius;
+        with_reaction(targets, bodies, primary, |j| {
+            let dx = bodies.position(j) - bodies.position(primary);
+            let r2 = dx.magnitude_squared();
+            let z = dx.dot(&self.pole);
+            factor/(r2*r2*r2.sqrt())*((1.0 - 5.0*z*z/r2)*dx + 2.0*z*self.pole)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+    use crate::simulation::integrator::ias15::Ias15;
+    use crate::simulation::integrator::Integrator;
+    use crate::simulation::tests::{period, relative, two_body};
+    use crate::simulation::World;
+
+    const PI: Real = std::f64::consts::PI as Real;
+
+    #[test]
+    fn nodal_precession() {
+        // dOmega/dt = -3/2*n*J2*(R/a)^2*cos(i)/(1 - e^2)^2
+        let (j2, radius, eccentricity) = (0.01, 0.5, 0.0);
+        let mut world = two_body(0.0, eccentricity);
+        world.forces.push(Box::new(Oblateness::new("sun", j2, radius)));
+        let elements = |world: &World| {
+            let (position, velocity) = relative(world);
+            Elements::from_state(position, velocity, 1.0)
+        };
+
+        let initial = elements(&world);
+        let mut integrator = Ias15::new();
+        for _ in 0..200 {
+            integrator.step(&mut world, period(0.0)/20.0);
+        }
+        let last = elements(&world);
+
+        let rate = -1.5*j2*radius*radius*initial.inclination.cos()/(1.0 - eccentricity*eccentricity).powi(2);
+        let expected = rate*10.0*period(0.0);
+        let precession = (last.longitude_of_ascending_node - initial.longitude_of_ascending_node + PI)
+            .rem_euclid(2.0*PI) - PI;
+        // The osculating node also wobbles within an orbit by about J2*(R/a)^2
+        assert!((precession - expected).abs() < 0.01*expected.abs(), "{} instead of {}", precession, expected);
+        assert!((last.inclination - initial.inclination).abs() < 1e-2);
+    }
+}
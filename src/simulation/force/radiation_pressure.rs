@@ -0,0 +1,108 @@
+use crate::simulation::force::Force;
+use crate::simulation::{gravity, Bodies, Vector};
+
+// Radiation pressure from a star on small bodies such as dust. Each body's coefficient beta
+// (`Body::radiation_pressure`) is the ratio of radiation pressure to the star's gravity on it, so
+// a = beta*G*M/r^2 directly away from the star. The star is not pushed back.
+pub struct RadiationPressure {
+    // Name of the body the light comes from
+    pub source: String,
+    // Also apply Poynting-Robertson drag, a = beta*G*M/r^2*((1 - r'/c)*r_hat - v/c), which makes
+    // dust spiral inwards. Needs `Gravity::speed_of_light`.
+    pub poynting_robertson: bool,
+}
+
+impl RadiationPressure {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            poynting_robertson: false,
+        }
+    }
+}
+
+impl Force for RadiationPressure {
+    fn name(&self) -> &'static str {
+        "radiation pressure"
+    }
+
+    fn accelerations(&self, targets: &[usize], bodies: &Bodies, gravity: &gravity::Gravity) -> Vec<Vector> {
+        let Some(source) = bodies.index_of(&self.source) else {
+            return vec![Vector::zeros(); targets.len()];
+        };
+        let c = if self.poynting_robertson {
+            Some(gravity.speed_of_light.expect("Poynting-Robertson drag needs the speed of light"))
+        } else {
+            None
+        };
+        let gm = gravity.g*bodies.mass[source];
+
+        targets.iter()
+            .map(|i| {
+                let beta = bodies.radiation_pressure[*i];
+                if beta == 0.0 || *i == source {
+                    return Vector::zeros();
+                }
+                let dx = bodies.position(*i) - bodies.position(source);
+                let r = dx.magnitude();
+                let direction = dx/r;
+                let magnitude = beta*gm/(r*r);
+                match c {
+                    Some(c) => {
+                        let v = bodies.velocity(*i) - bodies.velocity(source);
+                        magnitude*((1.0 - v.dot(&direction)/c)*direction - v/c)
+                    }
+                    None => magnitude*direction,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{Body, Real, World};
+
+    // A unit mass star at the origin and dust at (2, 0, 0) moving at `velocity`
+    fn dust(beta: Real, velocity: Vector) -> World {
+        let mut world = World::new();
+        world.add_body(Body::new("star"));
+        let mut dust = Body::new("dust");
+        dust.mass = 0.0;
+        dust.position = Vector::new(2.0, 0.0, 0.0);
+        dust.velocity = velocity;
+        dust.radiation_pressure = beta;
+        world.add_body(dust);
+        world.forces.push(Box::new(RadiationPressure::new("star")));
+        world
+    }
+
+    #[test]
+    fn balances_gravity() {
+        // Gravity is cut by a factor 1 - beta
+        for beta in [0.0, 0.5, 1.0] {
+            let mut world = dust(beta, Vector::zeros());
+            world.nbody();
+            let expected = Vector::new(-0.25*(1.0 - beta), 0.0, 0.0);
+            assert!((world.bodies.acceleration(1) - expected).magnitude() < 1e2*Real::EPSILON, "beta = {}", beta);
+            // The star is not pushed back
+            assert_eq!(world.bodies.acceleration(0), Vector::zeros());
+        }
+    }
+
+    #[test]
+    fn poynting_robertson_drag() {
+        let c = 100.0;
+        let velocity = Vector::new(0.1, 0.7, 0.0);
+        let mut world = dust(0.2, velocity);
+        world.gravity.speed_of_light = Some(c);
+        world.forces[0] = Box::new(RadiationPressure { poynting_robertson: true, ..RadiationPressure::new("star") });
+
+        // Against the velocity, 0.2/4*v/c, and the radial push cut by 1 - 0.1/c
+        let expected = 0.05*((1.0 - velocity.x/c)*Vector::x() - velocity/c);
+        let radiation = world.forces[0].accelerations(&[0, 1], &world.bodies, &world.gravity);
+        assert_eq!(radiation[0], Vector::zeros());
+        assert!((radiation[1] - expected).magnitude() < 1e2*Real::EPSILON, "{:?} instead of {:?}", radiation[1], expected);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::simulation::force::{with_reaction, Force};
+use crate::simulation::{gravity, Bodies, Real, Vector};
+
+// Tides raised on a primary by every other body, in the constant time lag model (Mignard 1979,
+// Hut 1981). The bulge points where the body was `time_lag` earlier as seen from the spinning
+// primary, so on top of the conservative pull there is a dissipative part:
+// a = -3*k2*G*m*R^5/r^10*(r^2*r + time_lag*(2*(r.v)*r + r^2*(r x spin + v)))
+// with r and v relative to the primary and m the body's own mass. Orbits inside corotation decay
+// and those outside are pushed out; eccentricity is damped.
+//
+// The primary takes the reaction, but its spin is held fixed rather than exchanging angular
+// momentum with the orbits, so this is only good while spin changes slowly.
+pub struct Tides {
+    // Name of the body the tides are raised on
+    pub primary: String,
+    // Potential Love number k2
+    pub love_number: Real,
+    pub radius: Real,
+    pub time_lag: Real,
+    // Angular velocity of the primary
+    pub spin: Vector,
+}
+
+impl Tides {
+    // Non-rotating primary
+    pub fn new(primary: &str, love_number: Real, radius: Real, time_lag: Real) -> Self {
+        Self {
+            primary: primary.to_string(),
+            love_number,
+            radius,
+            time_lag,
+            spin: Vector::zeros(),
+        }
+    }
+}
+
+impl Force for Tides {
+    fn name(&self) -> &'static str {
+        "constant time lag tides"
+    }
+
+    fn accelerations(&self, targets: &[usize], bodies: &Bodies, gravity: &gravity::Gravity) -> Vec<Vector> {
+        let Some(primary) = bodies.index_of(&self.primary) else {
+            return vec![Vector::zeros(); targets.len()];
+        };
+        let factor = -3.0*self.love_number*gravity.g*self.radius.powi(5);
+        with_reaction(targets, bodies, primary, |j| {
+            let dx = bodies.position(j) - bodies.position(primary);
+            let dv = bodies.velocity(j) - bodies.velocity(primary);
+            let r2 = dx.magnitude_squared();
+            let lagged = 2.0*dx.dot(&dv)*dx + r2*(dx.cross(&self.spin) + dv);
+            factor*bodies.mass[j]/(r2*r2*r2*r2*r2)*(r2*dx + self.time_lag*lagged)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+    use crate::simulation::integrator::ias15::Ias15;
+    use crate::simulation::integrator::Integrator;
+    use crate::simulation::tests::{period, relative, two_body};
+    use crate::simulation::World;
+
+    // Change in the semi-major axis of a circular orbit over ten orbits, with the primary spinning
+    // `spin` times as fast as the orbit, and the change expected
+    fn migration(spin: Real, time_lag: Real) -> (Real, Real) {
+        let (mass, love_number, radius) = (0.01, 0.3, 0.3);
+        let mut world = two_body(mass, 0.0);
+        let (position, velocity) = relative(&world);
+        let mut tides = Tides::new("sun", love_number, radius, time_lag);
+        tides.spin = spin*position.cross(&velocity)/position.magnitude_squared();
+        world.forces.push(Box::new(tides));
+
+        let semi_major_axis = |world: &World| {
+            let (position, velocity) = relative(world);
+            Elements::from_state(position, velocity, 1.0 + mass).semi_major_axis
+        };
+        let initial = semi_major_axis(&world);
+        let mut integrator = Ias15::new();
+        for _ in 0..200 {
+            integrator.step(&mut world, period(mass)/20.0);
+        }
+
+        // da/dt = -6*k2*G*m*(1 + m/M)*R^5*time_lag*(1 - spin/n)/a^7 for a circular orbit
+        let expected = -6.0*love_number*mass*(1.0 + mass)*radius.powi(5)*time_lag*(1.0 - spin)*10.0*period(mass);
+        (semi_major_axis(&world) - initial, expected)
+    }
+
+    #[test]
+    fn orbits_decay_inside_corotation() {
+        for spin in [0.0, 0.5] {
+            let (change, expected) = migration(spin, 0.1);
+            assert!(change < 0.0 && (change - expected).abs() < 0.05*expected.abs(), "{} instead of {}", change, expected);
+        }
+    }
+
+    #[test]
+    fn orbits_grow_outside_corotation() {
+        let (change, expected) = migration(2.0, 0.1);
+        assert!(change > 0.0 && (change - expected).abs() < 0.05*expected.abs(), "{} instead of {}", change, expected);
+    }
+
+    #[test]
+    fn no_migration_without_lag() {
+        // The conservative part alone
+        let (change, _) = migration(0.0, 0.0);
+        assert!(change.abs() < 1e-5, "{}", change);
+    }
+}
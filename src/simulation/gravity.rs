@@ -0,0 +1,266 @@
+pub mod barnes_hut;
+pub mod fmm;
+pub mod octree;
+pub mod particle_mesh;
+
+use crate::simulation::{parallel, softening, units, Bodies, Real, Vector};
+
+// Constants of the force law shared by every gravity solver
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gravity {
+    // Gravitational constant. Set from `units` by `set_units`, but may be overridden.
+    pub g: Real,
+    pub units: units::UnitSystem,
+    // Speed of light, only used by post-Newtonian terms. Set from `units` by `set_units`; N-body
+    // units have no natural value, so it must be set by hand for them.
+    pub speed_of_light: Option<Real>,
+
+    // Short range softening of gravity. Ignored by the Newtonian kernel.
+    pub kernel: softening::Kernel,
+    pub softening_length: Real,
+}
+
+impl Gravity {
+    pub fn new() -> Self {
+        Self {
+            g: units::UnitSystem::NBody.gravitational_constant() as Real,
+            units: units::UnitSystem::NBody,
+            speed_of_light: None,
+            kernel: softening::Kernel::Newtonian,
+            softening_length: 0.0,
+        }
+    }
+
+    pub fn set_units(&mut self, units: units::UnitSystem) {
+        self.units = units;
+        self.g = units.gravitational_constant() as Real;
+        self.speed_of_light = units.speed_of_light().map(|c| c as Real);
+    }
+
+    // Softening length between two bodies with optional overrides. Uses the larger of the two so
+    // that the force stays symmetric.
+    pub fn pair_softening(&self, softening0: Option<Real>, softening1: Option<Real>) -> Real {
+        softening0.unwrap_or(self.softening_length)
+            .max(softening1.unwrap_or(self.softening_length))
+    }
+
+    // Acceleration towards a point mass `mass` at offset `dx`, softened with `epsilon`
+    pub fn acceleration(&self, dx: Vector, mass: Real, epsilon: Real) -> Vector {
+        dx*self.g*mass*self.kernel.force_factor(dx.magnitude_squared(), epsilon)
+    }
+
+    // Time derivative of `acceleration` when the source moves at relative velocity `dv`
+    pub fn jerk(&self, dx: Vector, dv: Vector, mass: Real, epsilon: Real) -> Vector {
+        let r2 = dx.magnitude_squared();
+        let f = self.kernel.force_factor(r2, epsilon);
+        let g = self.kernel.jerk_factor(r2, epsilon);
+        (dv*f + dx*(g*dx.dot(&dv)))*self.g*mass
+    }
+
+    // Potential energy of two point masses at offset `dx`, softened with `epsilon`
+    pub fn potential(&self, dx: Vector, mass0: Real, mass1: Real, epsilon: Real) -> Real {
+        -self.g*mass0*mass1*self.kernel.potential_factor(dx.magnitude_squared(), epsilon)
+    }
+}
+
+impl Default for Gravity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Computes the gravitational acceleration of every body due to all others, in body order.
+// Solvers may keep state between calls (e.g. a tree to refit). Per-body work should go through
+// `parallel::map` so it is spread over the world's thread pool.
+pub trait GravitySolver: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn accelerations(&mut self, bodies: &Bodies, gravity: &Gravity) -> Vec<Vector>;
+
+    // Accelerations of just the bodies in `targets`, due to all bodies, in `targets` order. Used
+    // by individual timesteps, where only a few bodies are active at once. Solvers which can
+    // evaluate single bodies cheaply should override this.
+    fn accelerations_of(&mut self, targets: &[usize], bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        let all = self.accelerations(bodies, gravity);
+        targets.iter().map(|i| all[*i]).collect()
+    }
+
+    // The octree from the most recent solve, for solvers which build one. Lets other parts of
+    // the world, such as the collision broad phase, reuse its structure.
+    fn octree(&self) -> Option<&octree::Octree> {
+        None
+    }
+
+    // Gravitational potential at every body, in body order, for solvers whose forces are not
+    // those of the softened pairwise sum (e.g. periodic ones). None when they are, and energy
+    // can be summed pair by pair.
+    fn potentials(&self, _bodies: &Bodies, _gravity: &Gravity) -> Option<Vec<Real>> {
+        None
+    }
+}
+
+// Exact O(N^2) pairwise sum. The reference the approximate solvers are checked against.
+pub struct DirectSum;
+
+// Targets summed together by the direct sum. Each source is loaded once per block and applied to
+// every lane. The lanes are independent and fixed in number, with the kernel chosen once for the
+// whole loop, so the compiler may turn the loop over them into SIMD; nothing guarantees it does,
+// and the cubic spline's branches make it unlikely. Results are the same either way.
+const LANES: usize = 8;
+
+impl GravitySolver for DirectSum {
+    fn name(&self) -> &'static str {
+        "direct sum"
+    }
+
+    fn accelerations(&mut self, bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        let all: Vec<usize> = (0..bodies.len()).collect();
+        self.accelerations_of(&all, bodies, gravity)
+    }
+
+    fn accelerations_of(&mut self, targets: &[usize], bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        // One copy of the lane loop per kernel, rather than matching on it for every pair
+        use softening::Kernel;
+        match gravity.kernel {
+            Kernel::Newtonian => direct_sum(targets, bodies, gravity, |r2, e| Kernel::Newtonian.force_factor(r2, e)),
+            Kernel::Plummer => direct_sum(targets, bodies, gravity, |r2, e| Kernel::Plummer.force_factor(r2, e)),
+            Kernel::CubicSpline => direct_sum(targets, bodies, gravity, |r2, e| Kernel::CubicSpline.force_factor(r2, e)),
+        }
+    }
+}
+
+fn direct_sum(
+        targets: &[usize],
+        bodies: &Bodies,
+        gravity: &Gravity,
+        force_factor: impl Fn(Real, Real) -> Real + Sync + Send,
+    ) -> Vec<Vector>
+{
+    let softening: Vec<Real> = bodies.softening.iter()
+        .map(|epsilon| epsilon.unwrap_or(gravity.softening_length))
+        .collect();
+
+    let blocks = parallel::map(targets.len().div_ceil(LANES), |block| {
+        // Unused lanes of the last block sit at the origin and are dropped at the end
+        let block = &targets[block*LANES..targets.len().min((block + 1)*LANES)];
+        let mut x = [0.0; LANES];
+        let mut y = [0.0; LANES];
+        let mut z = [0.0; LANES];
+        let mut epsilon = [0.0; LANES];
+        for (l, i) in block.iter().enumerate() {
+            x[l] = bodies.x[*i];
+            y[l] = bodies.y[*i];
+            z[l] = bodies.z[*i];
+            epsilon[l] = softening[*i];
+        }
+
+        // No i != j test: a body's offset to itself is zero, so it adds nothing
+        let mut sum = [[0.0; LANES]; 3];
+        let sources = bodies.x.iter().zip(&bodies.y).zip(&bodies.z).zip(&bodies.mass).zip(&softening);
+        for ((((xj, yj), zj), mass), softening) in sources {
+            let mass = gravity.g*mass;
+            for l in 0..LANES {
+                let dx = xj - x[l];
+                let dy = yj - y[l];
+                let dz = zj - z[l];
+                let r2 = dx*dx + dy*dy + dz*dz;
+                let f = mass*force_factor(r2, epsilon[l].max(*softening));
+                sum[0][l] += f*dx;
+                sum[1][l] += f*dy;
+                sum[2][l] += f*dz;
+            }
+        }
+
+        (0..block.len()).map(|l| Vector::new(sum[0][l], sum[1][l], sum[2][l])).collect::<Vec<_>>()
+    });
+
+    blocks.into_iter().flatten().collect()
+}
+
+impl DirectSum {
+    // Acceleration and jerk (da/dt) of each body in `targets`, in `targets` order. The same
+    // pairwise sum as `accelerations`, with the relative velocity of every pair as well.
+    pub fn accelerations_and_jerks(
+            &self,
+            targets: &[usize],
+            bodies: &Bodies,
+            gravity: &Gravity,
+        ) -> Vec<(Vector, Vector)>
+    {
+        parallel::map(targets.len(), |t| {
+            let i = targets[t];
+            let (position, velocity) = (bodies.position(i), bodies.velocity(i));
+            let mut acceleration = Vector::zeros();
+            let mut jerk = Vector::zeros();
+            for j in 0..bodies.len() {
+                let epsilon = gravity.pair_softening(bodies.softening[i], bodies.softening[j]);
+                let dx = bodies.position(j) - position;
+                acceleration += gravity.acceleration(dx, bodies.mass[j], epsilon);
+                jerk += gravity.jerk(dx, bodies.velocity(j) - velocity, bodies.mass[j], epsilon);
+            }
+            (acceleration, jerk)
+        })
+    }
+}
+
+// Relative force errors of an approximate solver against a reference, over all bodies
+#[derive(Clone, Copy, Debug)]
+pub struct Accuracy {
+    pub rms: Real,
+    pub max: Real,
+}
+
+// Run both solvers on the same bodies and compare, e.g. `compare(&mut tree, &mut DirectSum, ..)`
+pub fn compare(
+        solver: &mut dyn GravitySolver,
+        reference: &mut dyn GravitySolver,
+        bodies: &Bodies,
+        gravity: &Gravity,
+    ) -> Accuracy
+{
+    let approximate = solver.accelerations(bodies, gravity);
+    let exact = reference.accelerations(bodies, gravity);
+
+    let mut sum_squares = 0.0;
+    let mut max: Real = 0.0;
+    let mut count = 0;
+    for (a, e) in approximate.iter().zip(&exact) {
+        let magnitude = e.magnitude();
+        if magnitude == 0.0 {
+            continue;
+        }
+        let error = (a - e).magnitude()/magnitude;
+        sum_squares += error*error;
+        max = max.max(error);
+        count += 1;
+    }
+
+    Accuracy {
+        rms: if count == 0 { 0.0 } else { (sum_squares/count as Real).sqrt() },
+        max,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{units, Body};
+
+    #[test]
+    fn direct_sum_is_inverse_square() {
+        let mut bodies = Bodies::new();
+        let mut heavy = Body::new("heavy");
+        heavy.mass = 3.0;
+        bodies.push(heavy);
+        let mut light = Body::new("light");
+        light.mass = 2.0;
+        light.position = Vector::new(0.0, 2.0, 0.0);
+        bodies.push(light);
+        let mut gravity = Gravity::new();
+        gravity.set_units(units::UnitSystem::SI);
+
+        let accelerations = DirectSum.accelerations(&bodies, &gravity);
+        let g = gravity.g;
+        assert!((accelerations[0] - Vector::new(0.0, g*2.0/4.0, 0.0)).magnitude() < 1e-6*g);
+        assert!((accelerations[1] - Vector::new(0.0, -g*3.0/4.0, 0.0)).magnitude() < 1e-6*g);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::simulation::gravity::octree::Octree;
+use crate::simulation::gravity::{Gravity, GravitySolver};
+use crate::simulation::{parallel, Bodies, Real, Vector};
+
+// O(N log N) tree solver (Barnes & Hut 1986)
+pub struct BarnesHut {
+    // Opening angle. Smaller is more accurate; 0 degenerates to direct summation.
+    pub theta: Real,
+    // Include quadrupole moments of accepted cells, not just their monopole
+    pub quadrupole: bool,
+    // Most bodies in a leaf before it is split
+    pub leaf_size: usize,
+    // Rebuild the tree every this many solves and only refit it in between. 1 rebuilds on
+    // every solve.
+    pub rebuild_interval: u32,
+
+    tree: Option<Octree>,
+    solves_since_rebuild: u32,
+}
+
+impl BarnesHut {
+    pub fn new(theta: Real) -> Self {
+        Self {
+            theta,
+            quadrupole: true,
+            leaf_size: 8,
+            rebuild_interval: 1,
+            tree: None,
+            solves_since_rebuild: 0,
+        }
+    }
+
+    // The tree used by the most recent solve, if any
+    pub fn tree(&self) -> Option<&Octree> {
+        self.tree.as_ref()
+    }
+
+    // Bring the tree up to date with `bodies`, rebuilding or refitting as configured
+    pub fn update_tree(&mut self, bodies: &Bodies, gravity: &Gravity) -> &Octree {
+        let stale = match &self.tree {
+            Some(tree) => tree.order.len() != bodies.len(),
+            None => true,
+        };
+
+        if stale || self.solves_since_rebuild + 1 >= self.rebuild_interval {
+            self.tree = Some(Octree::build(bodies, gravity, self.leaf_size));
+            self.solves_since_rebuild = 0;
+        } else {
+            self.tree.as_mut().unwrap().refit(bodies, gravity);
+            self.solves_since_rebuild += 1;
+        }
+
+        self.tree.as_ref().unwrap()
+    }
+}
+
+impl GravitySolver for BarnesHut {
+    fn name(&self) -> &'static str {
+        "Barnes-Hut"
+    }
+
+    fn accelerations(&mut self, bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        let (theta, quadrupole) = (self.theta, self.quadrupole);
+        let tree = self.update_tree(bodies, gravity);
+
+        parallel::map(bodies.len(), |i| tree.acceleration(i, bodies, gravity, theta, quadrupole))
+    }
+
+    fn accelerations_of(&mut self, targets: &[usize], bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        let (theta, quadrupole) = (self.theta, self.quadrupole);
+        let tree = self.update_tree(bodies, gravity);
+
+        parallel::map(targets.len(), |t| tree.acceleration(targets[t], bodies, gravity, theta, quadrupole))
+    }
+
+    fn octree(&self) -> Option<&Octree> {
+        self.tree()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::gravity::{compare, DirectSum};
+    use crate::simulation::tests::cluster;
+
+    #[test]
+    fn close_to_direct_sum() {
+        let bodies = cluster(1000, 1);
+        let gravity = Gravity::new();
+
+        let mut monopole = BarnesHut { quadrupole: false, ..BarnesHut::new(0.5) };
+        let monopole = compare(&mut monopole, &mut DirectSum, &bodies, &gravity);
+        let quadrupole = compare(&mut BarnesHut::new(0.5), &mut DirectSum, &bodies, &gravity);
+        assert!(monopole.rms < 1e-2, "{:?}", monopole);
+        assert!(quadrupole.rms < 2e-3, "{:?}", quadrupole);
+        assert!(quadrupole.rms < monopole.rms);
+    }
+
+    #[test]
+    fn opens_every_cell_at_zero_theta() {
+        let bodies = cluster(200, 2);
+        let accuracy = compare(&mut BarnesHut::new(0.0), &mut DirectSum, &bodies, &Gravity::new());
+        assert!(accuracy.max < 1e2*Real::EPSILON, "{:?}", accuracy);
+    }
+
+    #[test]
+    fn refits_between_rebuilds() {
+        let mut bodies = cluster(500, 3);
+        let gravity = Gravity::new();
+        let mut solver = BarnesHut { rebuild_interval: 4, ..BarnesHut::new(0.5) };
+        solver.accelerations(&bodies, &gravity);
+
+        // Small moves keep a refitted tree almost as accurate as a fresh one
+        for (i, x) in bodies.x.iter_mut().enumerate() {
+            *x += 0.01*(i as Real).sin();
+        }
+        let refitted = compare(&mut solver, &mut DirectSum, &bodies, &gravity);
+        assert!(refitted.rms < 2e-3, "{:?}", refitted);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::simulation::gravity::octree::Octree;
+use crate::simulation::gravity::{Gravity, GravitySolver};
+use crate::simulation::{parallel, Bodies, Real, Vector};
+
+// Fast multipole method with Cartesian Taylor expansions, evaluated with a dual tree walk over
+// the shared octree (in the style of Dehnen's falcON). Cost is close to O(N).
+//
+// With R = z_A - z_B between a target cell centre z_A and a source cell centre z_B,
+// the potential sum_j m_j/|x - y_j| at x = z_A + d expands as
+//     sum_k d^k/k! * L_k,    L_k = sum_n M_n * D_{n+k}(R),    M_n = sum_j m_j*(z_B - y_j)^n/n!
+// where n, k are multi-indices, D_n = d^n/dR^n (1/|R|) and terms are kept up to |n| + |k| <= order.
+
+// Enumerates the multi-indices (a, b, c) with a + b + c <= order
+struct MultiIndices {
+    order: usize,
+    terms: Vec<[usize; 3]>,
+    // Maps (a, b, c) to a position in `terms`
+    lookup: Vec<usize>,
+    // a!*b!*c! for every term
+    factorials: Vec<Real>,
+    // Every (n, k, n + k) with all three in range. Drives the translation operators.
+    sums: Vec<(usize, usize, usize)>,
+}
+
+impl MultiIndices {
+    fn new(order: usize) -> Self {
+        let side = order + 1;
+        let mut terms = vec![];
+        let mut lookup = vec![usize::MAX; side*side*side];
+
+        // Ordered by total degree so recurrences can run front to back
+        for degree in 0..=order {
+            for a in (0..=degree).rev() {
+                for b in (0..=degree - a).rev() {
+                    let c = degree - a - b;
+                    lookup[(a*side + b)*side + c] = terms.len();
+                    terms.push([a, b, c]);
+                }
+            }
+        }
+
+        let factorial = |n: usize| (1..=n).product::<usize>() as Real;
+        let factorials = terms.iter().map(|t| factorial(t[0])*factorial(t[1])*factorial(t[2])).collect();
+
+        let mut indices = Self { order, terms, lookup, factorials, sums: vec![] };
+        for (n, inner) in indices.terms.iter().enumerate() {
+            for (k, outer) in indices.terms.iter().enumerate() {
+                let sum = [(inner[0] + outer[0]) as i64, (inner[1] + outer[1]) as i64, (inner[2] + outer[2]) as i64];
+                if let Some(i) = indices.index(sum) {
+                    indices.sums.push((n, k, i));
+                }
+            }
+        }
+        indices
+    }
+
+    fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    // Position of (a, b, c), or None when a component is negative or the degree is too high
+    fn index(&self, term: [i64; 3]) -> Option<usize> {
+        if term.iter().any(|t| *t < 0) || term.iter().sum::<i64>() > self.order as i64 {
+            return None;
+        }
+        let side = self.order + 1;
+        Some(self.lookup[(term[0] as usize*side + term[1] as usize)*side + term[2] as usize])
+    }
+
+    // x^n/n! for every term
+    fn scaled_powers(&self, x: Vector) -> Vec<Real> {
+        let mut powers = [vec![1.0; self.order + 1], vec![1.0; self.order + 1], vec![1.0; self.order + 1]];
+        for (k, axis) in powers.iter_mut().enumerate() {
+            for n in 1..=self.order {
+                axis[n] = axis[n - 1]*x[k];
+            }
+        }
+        self.terms.iter()
+            .zip(&self.factorials)
+            .map(|(t, f)| powers[0][t[0]]*powers[1][t[1]]*powers[2][t[2]]/f)
+            .collect()
+    }
+
+    // Derivatives D_n(r) of 1/|r| for every term. Follows from differentiating
+    // |r|^2 * d_i(1/|r|) = -r_i/|r| n - 1 more times with the Leibniz rule.
+    fn derivatives(&self, r: Vector) -> Vec<Real> {
+        let r2 = r.magnitude_squared();
+        let mut d = vec![0.0; self.len()];
+        d[0] = 1.0/r2.sqrt();
+
+        let get = |d: &Vec<Real>, term: [i64; 3]| self.index(term).map_or(0.0, |i| d[i]);
+
+        for (index, term) in self.terms.iter().enumerate().skip(1) {
+            let n = [term[0] as i64, term[1] as i64, term[2] as i64];
+            // Peel one derivative off along the first axis that has one: n = n' + e_i
+            let i = (0..3).find(|k| n[*k] > 0).unwrap();
+            let mut reduced = n;
+            reduced[i] -= 1;
+
+            let shift = |term: [i64; 3], axis: usize, by: i64| {
+                let mut shifted = term;
+                shifted[axis] += by;
+                shifted
+            };
+
+            let mut sum = -r[i]*get(&d, reduced) - reduced[i] as Real*get(&d, shift(reduced, i, -1));
+            for j in 0..3 {
+                let nj = reduced[j] as Real;
+                if reduced[j] > 0 {
+                    sum -= 2.0*nj*r[j]*get(&d, shift(shift(reduced, j, -1), i, 1));
+                }
+                if reduced[j] > 1 {
+                    sum -= nj*(nj - 1.0)*get(&d, shift(shift(reduced, j, -2), i, 1));
+                }
+            }
+            d[index] = sum/r2;
+        }
+
+        d
+    }
+}
+
+pub struct FastMultipole {
+    // Expansion order p. Force errors fall roughly as theta^p.
+    pub order: usize,
+    // Two cells interact through their expansions when r_A + r_B < theta*|z_A - z_B|, where r is
+    // the radius of a cell around its expansion centre. Must be below 1, or overlapping cells
+    // would be expanded about each other.
+    pub theta: Real,
+    // Most bodies in a leaf before it is split
+    pub leaf_size: usize,
+
+    indices: MultiIndices,
+}
+
+// State of one solve. Per-cell vectors are indexed like `Octree::nodes`.
+struct Walk<'a> {
+    tree: &'a Octree,
+    bodies: &'a Bodies,
+
+    centres: Vec<Vector>,
+    radii: Vec<Real>,
+    multipoles: Vec<Vec<Real>>,
+    locals: Vec<Vec<Real>>,
+
+    // Interaction lists found by the dual tree walk, in the order they were found. `far` holds
+    // the source cells whose multipoles feed each target cell's local expansion, `near` the
+    // source leaves summed directly into each target leaf.
+    far: Vec<Vec<usize>>,
+    near: Vec<Vec<usize>>,
+}
+
+impl FastMultipole {
+    pub fn new(order: usize, theta: Real) -> Self {
+        assert!(theta > 0.0 && theta < 1.0, "multipole opening angle must be between 0 and 1");
+        Self {
+            order,
+            theta,
+            leaf_size: 16,
+            indices: MultiIndices::new(order),
+        }
+    }
+
+    // P2M at the leaves, M2M towards the root
+    fn upward(&self, walk: &mut Walk, index: usize) {
+        let (tree, bodies) = (walk.tree, walk.bodies);
+        let node = &tree.nodes[index];
+        let centre = node.centre_of_mass;
+        let mut multipole = vec![0.0; self.indices.len()];
+        let mut radius: Real = 0.0;
+
+        if node.is_leaf() {
+            for i in &tree.order[node.start..node.end] {
+                let offset = centre - bodies.position(*i);
+                radius = radius.max(offset.magnitude());
+                for (m, power) in multipole.iter_mut().zip(self.indices.scaled_powers(offset)) {
+                    *m += bodies.mass[*i]*power;
+                }
+            }
+        } else {
+            for child in node.children() {
+                self.upward(walk, child);
+
+                // M_n(z) = sum over k <= n of M_k(c)*(z - c)^(n-k)/(n-k)!
+                let shift = centre - walk.centres[child];
+                radius = radius.max(shift.magnitude() + walk.radii[child]);
+                let powers = self.indices.scaled_powers(shift);
+                let child_multipole = &walk.multipoles[child];
+                for (k, r, n) in &self.indices.sums {
+                    multipole[*n] += child_multipole[*k]*powers[*r];
+                }
+            }
+        }
+
+        walk.centres[index] = centre;
+        walk.radii[index] = radius;
+        walk.multipoles[index] = multipole;
+    }
+
+    // Dual tree walk deciding how source cell `b` acts on target cell `a`
+    fn interact(&self, walk: &mut Walk, a: usize, b: usize) {
+        let tree = walk.tree;
+        let (node_a, node_b) = (&tree.nodes[a], &tree.nodes[b]);
+        if node_b.mass == 0.0 {
+            return;
+        }
+
+        let r = walk.centres[a] - walk.centres[b];
+        let reach = walk.radii[a] + walk.radii[b];
+        if a != b && reach*reach < self.theta*self.theta*r.magnitude_squared() {
+            walk.far[a].push(b);
+            return;
+        }
+
+        if node_a.is_leaf() && node_b.is_leaf() {
+            walk.near[a].push(b);
+            return;
+        }
+
+        // Split the larger cell
+        if node_b.is_leaf() || (!node_a.is_leaf() && walk.radii[a] >= walk.radii[b]) {
+            for child in node_a.children() {
+                self.interact(walk, child, b);
+            }
+        } else {
+            for child in node_b.children() {
+                self.interact(walk, a, child);
+            }
+        }
+    }
+
+    // M2L: L_k = sum over sources of sum over n of M_n*D_{n+k}(R)
+    fn local_expansion(&self, walk: &Walk, a: usize) -> Vec<Real> {
+        let mut local = vec![0.0; self.indices.len()];
+        for b in &walk.far[a] {
+            let derivatives = self.indices.derivatives(walk.centres[a] - walk.centres[*b]);
+            let multipole = &walk.multipoles[*b];
+            for (n, k, d) in &self.indices.sums {
+                local[*k] += multipole[*n]*derivatives[*d];
+            }
+        }
+        local
+    }
+
+    // L2L towards the leaves
+    fn downward(&self, walk: &mut Walk, index: usize) {
+        for child in walk.tree.nodes[index].children() {
+            // L_m(c) = sum over k >= m of L_k(z)*(c - z)^(k-m)/(k-m)!
+            let powers = self.indices.scaled_powers(walk.centres[child] - walk.centres[index]);
+            let mut shifted = walk.locals[child].clone();
+            for (m, r, k) in &self.indices.sums {
+                shifted[*m] += walk.locals[index][*k]*powers[*r];
+            }
+            walk.locals[child] = shifted;
+            self.downward(walk, child);
+        }
+    }
+
+    // L2P from the body's leaf plus P2P from the leaf's near sources
+    fn body_acceleration(&self, walk: &Walk, gravity: &Gravity, leaf: usize, i: usize) -> Vector {
+        let (tree, bodies) = (walk.tree, walk.bodies);
+        let position = bodies.position(i);
+
+        // a_i = G*sum over k of L_{k+e_i}*d^k/k!
+        let local = &walk.locals[leaf];
+        let powers = self.indices.scaled_powers(position - walk.centres[leaf]);
+        let mut field = Vector::zeros();
+        for (k, term) in self.indices.terms.iter().enumerate() {
+            for axis in 0..3 {
+                let mut raised = [term[0] as i64, term[1] as i64, term[2] as i64];
+                raised[axis] += 1;
+                if let Some(l) = self.indices.index(raised) {
+                    field[axis] += local[l]*powers[k];
+                }
+            }
+        }
+        let mut sum = gravity.g*field;
+
+        for source in &walk.near[leaf] {
+            let node = &tree.nodes[*source];
+            for j in &tree.order[node.start..node.end] {
+                if i != *j {
+                    let epsilon = gravity.pair_softening(bodies.softening[i], bodies.softening[*j]);
+                    sum += gravity.acceleration(bodies.position(*j) - position, bodies.mass[*j], epsilon);
+                }
+            }
+        }
+
+        sum
+    }
+}
+
+impl GravitySolver for FastMultipole {
+    fn name(&self) -> &'static str {
+        "fast multipole"
+    }
+
+    fn accelerations(&mut self, bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        if self.indices.order != self.order {
+            self.indices = MultiIndices::new(self.order);
+        }
+
+        if bodies.is_empty() {
+            return vec![];
+        }
+
+        let tree = Octree::build(bodies, gravity, self.leaf_size);
+        let count = tree.nodes.len();
+        let mut walk = Walk {
+            tree: &tree,
+            bodies,
+            centres: vec![Vector::zeros(); count],
+            radii: vec![0.0; count],
+            multipoles: vec![vec![]; count],
+            locals: vec![],
+            far: vec![vec![]; count],
+            near: vec![vec![]; count],
+        };
+
+        // The walk itself is serial and cheap; the expensive M2L and P2P work afterwards runs
+        // per cell and per body, each summing its own interaction list in order.
+        self.upward(&mut walk, 0);
+        self.interact(&mut walk, 0, 0);
+        walk.locals = parallel::map(count, |a| self.local_expansion(&walk, a));
+        self.downward(&mut walk, 0);
+
+        let mut leaf_of = vec![0; bodies.len()];
+        for (index, node) in tree.nodes.iter().enumerate() {
+            if node.is_leaf() {
+                for i in &tree.order[node.start..node.end] {
+                    leaf_of[*i] = index;
+                }
+            }
+        }
+
+        parallel::map(bodies.len(), |i| self.body_acceleration(&walk, gravity, leaf_of[i], i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::gravity::{compare, DirectSum};
+    use crate::simulation::tests::cluster;
+
+    #[test]
+    fn close_to_direct_sum() {
+        let bodies = cluster(2000, 4);
+        let gravity = Gravity::new();
+
+        // About 8e-4 rms at order 4
+        let accuracy = compare(&mut FastMultipole::new(4, 0.5), &mut DirectSum, &bodies, &gravity);
+        assert!(accuracy.rms < 2e-3, "{:?}", accuracy);
+    }
+
+    #[test]
+    fn higher_order_is_more_accurate() {
+        let bodies = cluster(1000, 5);
+        let gravity = Gravity::new();
+
+        let errors: Vec<Real> = [2, 4, 6].into_iter()
+            .map(|order| compare(&mut FastMultipole::new(order, 0.5), &mut DirectSum, &bodies, &gravity).rms)
+            .collect();
+        assert!(errors[1] < errors[0] && errors[2] < errors[1], "{:?}", errors);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_wide_opening_angle() {
+        FastMultipole::new(4, 1.0);
+    }
+}
@@ -0,0 +1,280 @@
+use nalgebra as na;
+
+use crate::simulation::gravity::Gravity;
+use crate::simulation::{Bodies, Real, Vector};
+
+type Matrix = na::Matrix3<Real>;
+
+// Marks a missing child
+const EMPTY: u32 = u32::MAX;
+// Coincident bodies would otherwise be split forever
+const MAX_DEPTH: u32 = 48;
+
+pub struct Node {
+    // Tight bounding box of the bodies below this node. Recomputed on refit, so it stays valid
+    // when bodies drift out of the octant they were sorted into.
+    pub min: Vector,
+    pub max: Vector,
+
+    pub mass: Real,
+    pub centre_of_mass: Vector,
+    // Traceless quadrupole moment about the centre of mass: sum of m*(3*x*x^T - |x|^2*I)
+    pub quadrupole: Matrix,
+    // Largest softening length of any body below this node
+    pub softening: Real,
+
+    pub children: [u32; 8],
+    // Range into `Octree::order` holding the bodies below this node
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Node {
+    pub fn is_leaf(&self) -> bool {
+        self.children.iter().all(|child| *child == EMPTY)
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = usize> + '_ {
+        self.children.iter().filter(|child| **child != EMPTY).map(|child| *child as usize)
+    }
+
+    pub fn contains(&self, point: &Vector) -> bool {
+        (0..3).all(|k| point[k] >= self.min[k] && point[k] <= self.max[k])
+    }
+
+    // Longest side of the bounding box. Used as the cell size in the opening criterion.
+    pub fn size(&self) -> Real {
+        (self.max - self.min).max()
+    }
+}
+
+// Octree over body positions. Node 0 is the root.
+pub struct Octree {
+    pub nodes: Vec<Node>,
+    // Body indices, arranged so every node's bodies are contiguous
+    pub order: Vec<usize>,
+    // Most bodies in a leaf before it is split
+    pub leaf_size: usize,
+}
+
+impl Octree {
+    pub fn build(bodies: &Bodies, gravity: &Gravity, leaf_size: usize) -> Self {
+        let mut tree = Self {
+            nodes: vec![],
+            order: (0..bodies.len()).collect(),
+            leaf_size: leaf_size.max(1),
+        };
+        if bodies.is_empty() {
+            return tree;
+        }
+
+        // Root cube enclosing every body
+        let mut min = bodies.position(0);
+        let mut max = bodies.position(0);
+        for i in 0..bodies.len() {
+            min = min.inf(&bodies.position(i));
+            max = max.sup(&bodies.position(i));
+        }
+        let centre = 0.5*(min + max);
+        let half_width = 0.5*(max - min).max()*1.001 + Real::EPSILON;
+
+        tree.build_node(bodies, 0, bodies.len(), centre, half_width, 0);
+        tree.refit(bodies, gravity);
+        tree
+    }
+
+    fn build_node(
+            &mut self,
+            bodies: &Bodies,
+            start: usize,
+            end: usize,
+            centre: Vector,
+            half_width: Real,
+            depth: u32,
+        ) -> u32
+    {
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            min: centre, max: centre,
+            mass: 0.0,
+            centre_of_mass: centre,
+            quadrupole: Matrix::zeros(),
+            softening: 0.0,
+            children: [EMPTY; 8],
+            start, end,
+        });
+
+        if end - start <= self.leaf_size || depth >= MAX_DEPTH {
+            return index as u32;
+        }
+
+        // Bit k of the octant is set when the body is above the centre along axis k
+        let octant = |position: &Vector| -> usize {
+            (0..3).map(|k| ((position[k] > centre[k]) as usize) << k).sum()
+        };
+        self.order[start..end].sort_by_key(|i| octant(&bodies.position(*i)));
+
+        let mut child_start = start;
+        for o in 0..8 {
+            let mut child_end = child_start;
+            while child_end < end && octant(&bodies.position(self.order[child_end])) == o {
+                child_end += 1;
+            }
+            if child_end > child_start {
+                let offset = Vector::from_fn(|k, _| if o >> k & 1 == 1 { 0.5 } else { -0.5 });
+                let child = self.build_node(
+                    bodies, child_start, child_end,
+                    centre + offset*half_width, 0.5*half_width,
+                    depth + 1
+                );
+                self.nodes[index].children[o] = child;
+            }
+            child_start = child_end;
+        }
+
+        index as u32
+    }
+
+    // Recompute masses, moments and bounding boxes from current body positions while keeping
+    // the tree structure. Much cheaper than a rebuild, but cells loosen as bodies move.
+    pub fn refit(&mut self, bodies: &Bodies, gravity: &Gravity) {
+        if !self.nodes.is_empty() {
+            self.compute_moments(0, bodies, gravity);
+        }
+    }
+
+    fn compute_moments(&mut self, index: usize, bodies: &Bodies, gravity: &Gravity) {
+        let node = &self.nodes[index];
+        let (start, end) = (node.start, node.end);
+
+        if node.is_leaf() {
+            let first = bodies.position(self.order[start]);
+            let (mut min, mut max) = (first, first);
+            let mut mass = 0.0;
+            let mut weighted = Vector::zeros();
+            let mut softening: Real = 0.0;
+            for i in &self.order[start..end] {
+                let position = bodies.position(*i);
+                min = min.inf(&position);
+                max = max.sup(&position);
+                mass += bodies.mass[*i];
+                weighted += bodies.mass[*i]*position;
+                softening = softening.max(bodies.softening[*i].unwrap_or(gravity.softening_length));
+            }
+            let centre_of_mass = if mass > 0.0 { weighted/mass } else { 0.5*(min + max) };
+
+            let mut quadrupole = Matrix::zeros();
+            for i in &self.order[start..end] {
+                quadrupole += point_quadrupole(bodies.position(*i) - centre_of_mass, bodies.mass[*i]);
+            }
+
+            let node = &mut self.nodes[index];
+            node.min = min;
+            node.max = max;
+            node.mass = mass;
+            node.centre_of_mass = centre_of_mass;
+            node.quadrupole = quadrupole;
+            node.softening = softening;
+            return;
+        }
+
+        let children: Vec<usize> = node.children().collect();
+        for child in &children {
+            self.compute_moments(*child, bodies, gravity);
+        }
+
+        let first = &self.nodes[children[0]];
+        let (mut min, mut max) = (first.min, first.max);
+        let mut mass = 0.0;
+        let mut weighted = Vector::zeros();
+        let mut softening: Real = 0.0;
+        for child in &children {
+            let child = &self.nodes[*child];
+            min = min.inf(&child.min);
+            max = max.sup(&child.max);
+            mass += child.mass;
+            weighted += child.mass*child.centre_of_mass;
+            softening = softening.max(child.softening);
+        }
+        let centre_of_mass = if mass > 0.0 { weighted/mass } else { 0.5*(min + max) };
+
+        // Parallel axis theorem moves each child's moment to the new centre of mass
+        let mut quadrupole = Matrix::zeros();
+        for child in &children {
+            let child = &self.nodes[*child];
+            quadrupole += child.quadrupole + point_quadrupole(child.centre_of_mass - centre_of_mass, child.mass);
+        }
+
+        let node = &mut self.nodes[index];
+        node.min = min;
+        node.max = max;
+        node.mass = mass;
+        node.centre_of_mass = centre_of_mass;
+        node.quadrupole = quadrupole;
+        node.softening = softening;
+    }
+
+    // Acceleration of body `target` by walking the tree. A cell is used as a whole when
+    // size/distance < theta and the body is outside its bounding box; otherwise it is opened.
+    pub fn acceleration(
+            &self,
+            target: usize,
+            bodies: &Bodies,
+            gravity: &Gravity,
+            theta: Real,
+            quadrupole: bool,
+        ) -> Vector
+    {
+        let mut sum = Vector::zeros();
+        if self.nodes.is_empty() {
+            return sum;
+        }
+
+        let position = bodies.position(target);
+        let softening = bodies.softening[target];
+
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.mass == 0.0 {
+                continue;
+            }
+
+            if node.is_leaf() {
+                for i in &self.order[node.start..node.end] {
+                    if *i != target {
+                        let epsilon = gravity.pair_softening(softening, bodies.softening[*i]);
+                        sum += gravity.acceleration(bodies.position(*i) - position, bodies.mass[*i], epsilon);
+                    }
+                }
+                continue;
+            }
+
+            let dx = node.centre_of_mass - position;
+            let r2 = dx.magnitude_squared();
+            let size = node.size();
+            if node.contains(&position) || size*size >= theta*theta*r2 {
+                stack.extend(node.children());
+                continue;
+            }
+
+            let epsilon = gravity.pair_softening(softening, Some(node.softening));
+            sum += gravity.acceleration(dx, node.mass, epsilon);
+
+            if quadrupole {
+                // a = G*(Q*x/r^5 - 5/2*(x^T*Q*x)*x/r^7), x pointing from the cell to the body
+                let x = -dx;
+                let r5 = r2*r2*r2.sqrt();
+                let qx = node.quadrupole*x;
+                sum += gravity.g*(qx/r5 - 2.5*x.dot(&qx)*x/(r5*r2));
+            }
+        }
+
+        sum
+    }
+}
+
+// Quadrupole moment of a point mass at offset x
+fn point_quadrupole(x: Vector, mass: Real) -> Matrix {
+    mass*(3.0*x*x.transpose() - Matrix::identity()*x.magnitude_squared())
+}
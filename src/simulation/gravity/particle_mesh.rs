@@ -0,0 +1,353 @@
+use nalgebra as na;
+
+use crate::simulation::gravity::{Gravity, GravitySolver};
+use crate::simulation::{parallel, Bodies, Real, Vector};
+
+type Complex = na::Complex<Real>;
+
+// How mass is spread onto (and forces read back from) the grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assignment {
+    // Cloud in cell: linear weights over the 2x2x2 nearest grid points
+    CloudInCell,
+    // Triangular shaped cloud: quadratic weights over the 3x3x3 nearest grid points. Smoother
+    // forces with less grid anisotropy, at about three times the cost.
+    TriangularShapedCloud,
+}
+
+impl Assignment {
+    // First grid point and weights along one axis for a position `u` in grid units
+    fn weights(&self, u: Real) -> (i64, [Real; 3]) {
+        match self {
+            Assignment::CloudInCell => {
+                let first = u.floor();
+                let f = u - first;
+                (first as i64, [1.0 - f, f, 0.0])
+            }
+            Assignment::TriangularShapedCloud => {
+                let nearest = u.round();
+                let d = u - nearest;
+                (nearest as i64 - 1, [0.5*(0.5 - d)*(0.5 - d), 0.75 - d*d, 0.5*(0.5 + d)*(0.5 + d)])
+            }
+        }
+    }
+
+    // Fourier transform of the assignment window along one axis, for deconvolution
+    fn window(&self, k: Real, spacing: Real) -> Real {
+        let x = 0.5*k*spacing;
+        let sinc = if x == 0.0 { 1.0 } else { x.sin()/x };
+        match self {
+            Assignment::CloudInCell => sinc*sinc,
+            Assignment::TriangularShapedCloud => sinc*sinc*sinc,
+        }
+    }
+}
+
+// Particle-mesh gravity in a periodic cube [0, box_size)^3. Mass is assigned to a grid, Poisson's
+// equation is solved with FFTs and the resulting field is interpolated back to the bodies.
+// Forces are softened on the scale of a grid cell, so `Gravity::kernel` is not used. Pair with
+// `World::periodic_box` so bodies are wrapped back into the same box.
+pub struct ParticleMesh {
+    // Cells along each side. Must be a power of two.
+    pub grid: usize,
+    pub box_size: Real,
+    pub assignment: Assignment,
+    // Divide out the assignment window twice (once for assignment, once for interpolation).
+    // This boosts modes near the grid's Nyquist frequency by up to a few hundred times, so it is
+    // only useful on top of a short range filter, e.g. the long range half of a TreePM split. On
+    // its own it ruins forces within a few cells of a body. Off by default.
+    pub deconvolve: bool,
+}
+
+impl ParticleMesh {
+    pub fn new(grid: usize, box_size: Real) -> Self {
+        assert!(grid.is_power_of_two(), "particle mesh grid must be a power of two");
+        Self {
+            grid,
+            box_size,
+            assignment: Assignment::CloudInCell,
+            deconvolve: false,
+        }
+    }
+
+    // Calls `f` with the flat grid index and weight of every grid point a body at `position`
+    // touches, wrapping periodically
+    fn stencil(&self, position: &Vector, mut f: impl FnMut(usize, Real)) {
+        let n = self.grid as i64;
+        let spacing = self.box_size/self.grid as Real;
+        let axes: Vec<(i64, [Real; 3])> = (0..3)
+            .map(|k| self.assignment.weights(position[k].rem_euclid(self.box_size)/spacing))
+            .collect();
+        let width = match self.assignment {
+            Assignment::CloudInCell => 2,
+            Assignment::TriangularShapedCloud => 3,
+        };
+
+        for a in 0..width {
+            for b in 0..width {
+                for c in 0..width {
+                    let x = (axes[0].0 + a as i64).rem_euclid(n) as usize;
+                    let y = (axes[1].0 + b as i64).rem_euclid(n) as usize;
+                    let z = (axes[2].0 + c as i64).rem_euclid(n) as usize;
+                    f((x*self.grid + y)*self.grid + z, axes[0].1[a]*axes[1].1[b]*axes[2].1[c]);
+                }
+            }
+        }
+    }
+
+    // Fourier transform of the density on the grid. Mass assignment is kept serial: bodies
+    // scatter onto shared cells, and summing them in a fixed order keeps results independent of
+    // the thread count.
+    fn density(&self, bodies: &Bodies) -> Vec<Complex> {
+        let n = self.grid;
+        let spacing = self.box_size/n as Real;
+        let cell_volume = spacing*spacing*spacing;
+
+        let mut density = vec![Complex::new(0.0, 0.0); n*n*n];
+        for b in 0..bodies.len() {
+            let mass = bodies.mass[b];
+            self.stencil(&bodies.position(b), |i, w| density[i].re += w*mass/cell_volume);
+        }
+        fft3(&mut density, n, false);
+        density
+    }
+
+    // -phi_k/rho_k from Poisson's equation, phi_k = -4*pi*G*rho_k/k^2, with the assignment window
+    // divided out if deconvolving. The k = 0 mode is dropped, i.e. forces come from density
+    // relative to the mean.
+    fn green(&self, k: Vector, gravity: &Gravity) -> Real {
+        let k2 = k.magnitude_squared();
+        if k2 == 0.0 {
+            return 0.0;
+        }
+        let mut scale = 4.0*std::f64::consts::PI as Real*gravity.g/k2;
+        if self.deconvolve {
+            let spacing = self.box_size/self.grid as Real;
+            let w = self.assignment.window(k.x, spacing)
+                *self.assignment.window(k.y, spacing)
+                *self.assignment.window(k.z, spacing);
+            scale /= w*w;
+        }
+        scale
+    }
+
+    // Wavenumber of grid mode `i` along one axis
+    fn wavenumber(&self, i: usize) -> Real {
+        let signed = if i < self.grid/2 { i as i64 } else { i as i64 - self.grid as i64 };
+        2.0*std::f64::consts::PI as Real*signed as Real/self.box_size
+    }
+}
+
+impl GravitySolver for ParticleMesh {
+    fn name(&self) -> &'static str {
+        "particle mesh"
+    }
+
+    fn accelerations(&mut self, bodies: &Bodies, gravity: &Gravity) -> Vec<Vector> {
+        let n = self.grid;
+        let spacing = self.box_size/n as Real;
+        let density = self.density(bodies);
+
+        // a_k = -D(k)*phi_k per axis. D(k) is the four point central difference in Fourier
+        // space rather than i*k, which would make the field of a single cell ring from cell to
+        // cell.
+        let difference = |k: Real| ((4.0/3.0)*(k*spacing).sin() - (1.0/6.0)*(2.0*k*spacing).sin())/spacing;
+        let mut field = [density.clone(), density.clone(), density];
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let i = (x*n + y)*n + z;
+                    let k = Vector::new(self.wavenumber(x), self.wavenumber(y), self.wavenumber(z));
+                    let scale = self.green(k, gravity);
+                    for axis in 0..3 {
+                        // -i*D*phi_k = i*D*scale*rho_k
+                        let d = difference(k[axis]);
+                        let rho = field[axis][i];
+                        field[axis][i] = Complex::new(-d*scale*rho.im, d*scale*rho.re);
+                    }
+                }
+            }
+        }
+        for component in &mut field {
+            fft3(component, n, true);
+        }
+
+        // Interpolate back with the same stencil so there is no self force
+        parallel::map(bodies.len(), |b| {
+            let mut acceleration = Vector::zeros();
+            self.stencil(&bodies.position(b), |i, w| {
+                for axis in 0..3 {
+                    acceleration[axis] += w*field[axis][i].re;
+                }
+            });
+            acceleration
+        })
+    }
+
+    // The periodic potential the forces come from, read back with the same stencil. It includes
+    // each body's own field smoothed over the grid, which is nearly constant and cancels out of
+    // energy differences.
+    fn potentials(&self, bodies: &Bodies, gravity: &Gravity) -> Option<Vec<Real>> {
+        let n = self.grid;
+        let mut potential = self.density(bodies);
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let k = Vector::new(self.wavenumber(x), self.wavenumber(y), self.wavenumber(z));
+                    potential[(x*n + y)*n + z] *= -self.green(k, gravity);
+                }
+            }
+        }
+        fft3(&mut potential, n, true);
+
+        Some(parallel::map(bodies.len(), |b| {
+            let mut phi = 0.0;
+            self.stencil(&bodies.position(b), |i, w| phi += w*potential[i].re);
+            phi
+        }))
+    }
+}
+
+// In-place radix-2 FFT. The inverse is normalised by 1/len.
+fn fft(data: &mut [Complex], inverse: bool) {
+    let len = data.len();
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..len {
+        let mut bit = len >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut size = 2;
+    while size <= len {
+        let angle = sign*2.0*std::f64::consts::PI/size as f64;
+        for start in (0..len).step_by(size) {
+            for k in 0..size/2 {
+                let twiddle = Complex::new((angle*k as f64).cos() as Real, (angle*k as f64).sin() as Real);
+                let even = data[start + k];
+                let odd = data[start + k + size/2]*twiddle;
+                data[start + k] = even + odd;
+                data[start + k + size/2] = even - odd;
+            }
+        }
+        size <<= 1;
+    }
+
+    if inverse {
+        let scale = 1.0/len as Real;
+        data.iter_mut().for_each(|x| *x *= scale);
+    }
+}
+
+// 3D FFT of an n*n*n grid stored x-major, one axis at a time
+fn fft3(data: &mut [Complex], n: usize, inverse: bool) {
+    let mut line = vec![Complex::new(0.0, 0.0); n];
+    for stride in [1, n, n*n] {
+        for base in 0..n*n*n {
+            // Visit each line once, from its first element
+            if (base/stride) % n != 0 {
+                continue;
+            }
+            for (k, value) in line.iter_mut().enumerate() {
+                *value = data[base + k*stride];
+            }
+            fft(&mut line, inverse);
+            for (k, value) in line.iter().enumerate() {
+                data[base + k*stride] = *value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Body;
+
+    #[test]
+    fn newtonian_beyond_a_few_cells() {
+        let box_size = 10.0;
+        let mut mesh = ParticleMesh::new(64, box_size);
+        let directions = [Vector::x(), Vector::new(0.8, 0.6, 0.0), Vector::new(1.0, 1.0, 1.0).normalize()];
+        let separations = [0.75, 1.0, 1.5, 2.0, 3.0];
+
+        // A unit mass on a grid point and off them, with massless bodies around it from 5 to 19
+        // cells of 0.156 away, along an axis, a face diagonal and a body diagonal
+        for origin in [Vector::zeros(), Vector::new(3.03, 4.01, 5.07)] {
+            let mut bodies = Bodies::new();
+            let mut source = Body::new("source");
+            source.position = origin;
+            bodies.push(source);
+            for direction in directions {
+                for r in separations {
+                    let mut body = Body::new(&bodies.len().to_string());
+                    body.mass = 0.0;
+                    body.position = origin + direction*r;
+                    bodies.push(body);
+                }
+            }
+
+            let accelerations = mesh.accelerations(&bodies, &Gravity::new());
+            let pairs = directions.iter().flat_map(|d| separations.iter().map(move |r| (*d, *r)));
+            for ((direction, r), acceleration) in pairs.zip(&accelerations[1..]) {
+                // Newton, plus the outward push of the mean density subtracted from the box. What
+                // is left over is grid anisotropy and the higher order pull of the periodic
+                // images, up to about 5%.
+                let pi = std::f64::consts::PI as Real;
+                let expected = -1.0/(r*r) + 4.0/3.0*pi*r/(box_size*box_size*box_size);
+                let radial = acceleration.dot(&direction);
+                assert!(
+                    (radial/expected - 1.0).abs() < 0.06,
+                    "{} at r = {} from {:?} along {:?}, expected {}", radial, r, origin, direction, expected,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn equal_and_opposite() {
+        let mut bodies = Bodies::new();
+        for (name, x) in [("first", 1.0), ("second", 2.3)] {
+            let mut body = Body::new(name);
+            body.position = Vector::new(x, 0.7, 0.2);
+            bodies.push(body);
+        }
+
+        let accelerations = ParticleMesh::new(16, 4.0).accelerations(&bodies, &Gravity::new());
+        assert!((accelerations[0] + accelerations[1]).magnitude() < 1e2*Real::EPSILON*accelerations[0].magnitude());
+    }
+
+    #[test]
+    fn potential_differences_are_newtonian() {
+        // Two unit masses on grid points 7 and then 14 cells apart, so their smoothed self
+        // potentials are the same both times
+        let (box_size, grid) = (10.0, 64);
+        let spacing = box_size/grid as Real;
+        let energy = |cells: Real| {
+            let mut bodies = Bodies::new();
+            for (name, x) in [("first", 0.0), ("second", cells*spacing)] {
+                let mut body = Body::new(name);
+                body.position = Vector::new(x + 3.0*spacing, 4.0*spacing, 5.0*spacing);
+                bodies.push(body);
+            }
+            let potentials = ParticleMesh::new(grid, box_size).potentials(&bodies, &Gravity::new()).unwrap();
+            0.5*(potentials[0] + potentials[1])
+        };
+
+        // Newton, plus the potential of the mean density subtracted from the box
+        let pair = |r: Real| -1.0/r + 2.0/3.0*std::f64::consts::PI as Real*r*r/(box_size*box_size*box_size);
+        let (near, far) = (7.0*spacing, 14.0*spacing);
+        let expected = pair(far) - pair(near);
+        let measured = energy(14.0) - energy(7.0);
+        assert!((measured/expected - 1.0).abs() < 0.02, "{} expected {}", measured, expected);
+    }
+}
@@ -0,0 +1,284 @@
+pub mod block;
+pub mod hermite;
+pub mod ias15;
+pub mod wisdom_holman;
+
+use crate::simulation::{gravity, Kind, Real, Vector, World};
+
+// An integrator advances every body in a world by one step of length `delta`.
+// Integrators only need `World::nbody` (or `World::nbody_of`) to evaluate accelerations at the
+// current state, so any of them can be swapped in between steps.
+pub trait Integrator {
+    fn name(&self) -> &'static str;
+    fn step(&mut self, world: &mut World, delta: Real);
+}
+
+// First order. v += a*dt, then x += v*dt using the updated velocity.
+// Kept for comparison; energy drifts quickly.
+pub struct SemiImplicitEuler;
+
+impl Integrator for SemiImplicitEuler {
+    fn name(&self) -> &'static str {
+        "semi-implicit Euler"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        world.nbody();
+        world.kick(delta);
+        world.drift(delta);
+    }
+}
+
+// What the accelerations left in a world by the end of a step were evaluated from. Leapfrog and
+// velocity Verlet close a step with a force evaluation at the new positions, which is exactly the
+// one the next step opens with, so while nothing has changed in between it is reused and each
+// step costs one evaluation instead of two.
+//
+// Anything moving a body or changing what it pulls with between steps (collisions, recentring,
+// pinned paths, mass or softening edits, a new solver) forces a fresh evaluation. So do velocity
+// dependent forces, as the closing evaluation saw the half step velocities, and regularisation,
+// as pairs are picked up without moving.
+#[derive(Default)]
+struct ClosingForces {
+    positions: Vec<Vector>,
+    sources: Sources,
+}
+
+impl ClosingForces {
+    // Make sure the world's accelerations are those of its current state
+    fn open(&mut self, world: &mut World) {
+        let reusable = world.post_newtonian.is_none()
+            && world.forces.is_empty()
+            && world.regularisation.is_none()
+            && self.positions == world.positions()
+            && self.sources == Sources::of(world);
+        if !reusable {
+            world.nbody();
+        }
+    }
+
+    // Evaluate the accelerations at the end of a step and remember what they came from
+    fn close(&mut self, world: &mut World) {
+        world.nbody();
+        self.positions = world.positions();
+        self.sources = Sources::of(world);
+    }
+}
+
+// Everything besides positions that gravity in a world is summed from, for integrators which
+// keep forces (or their derivatives) from one step to the next and must notice edits in between
+#[derive(Default, PartialEq)]
+pub(super) struct Sources {
+    masses: Vec<Real>,
+    softening: Vec<Option<Real>>,
+    kinds: Vec<std::mem::Discriminant<Kind>>,
+    gravity: Option<gravity::Gravity>,
+    // Name and address of the solver. A replacement is allocated before the old one is dropped,
+    // so it can't share the address; zero sized solvers can, but have no settings to differ in.
+    solver: Option<(&'static str, usize)>,
+}
+
+impl Sources {
+    pub(super) fn of(world: &World) -> Self {
+        Self {
+            masses: world.bodies.mass.clone(),
+            softening: world.bodies.softening.clone(),
+            kinds: world.bodies.kind.iter().map(std::mem::discriminant).collect(),
+            gravity: Some(world.gravity),
+            solver: Some((world.solver.name(), &*world.solver as *const dyn gravity::GravitySolver as *const () as usize)),
+        }
+    }
+}
+
+// Second order, symplectic kick-drift-kick leapfrog
+#[derive(Default)]
+pub struct Leapfrog {
+    closing: ClosingForces,
+}
+
+impl Leapfrog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Integrator for Leapfrog {
+    fn name(&self) -> &'static str {
+        "leapfrog (KDK)"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        self.closing.open(world);
+        world.kick(0.5*delta);
+        world.drift(delta);
+        self.closing.close(world);
+        world.kick(0.5*delta);
+    }
+}
+
+// Second order, symplectic. Algebraically the same trajectory as KDK leapfrog, but the position
+// update uses the acceleration directly: x += v*dt + a*dt^2/2, then v += (a_old + a_new)*dt/2.
+#[derive(Default)]
+pub struct VelocityVerlet {
+    closing: ClosingForces,
+}
+
+impl VelocityVerlet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Integrator for VelocityVerlet {
+    fn name(&self) -> &'static str {
+        "velocity Verlet"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        self.closing.open(world);
+        let b = &mut world.bodies;
+        for (x, (v, a)) in [(&mut b.x, (&b.vx, &b.ax)), (&mut b.y, (&b.vy, &b.ay)), (&mut b.z, (&b.vz, &b.az))] {
+            for i in 0..x.len() {
+                x[i] += delta*v[i] + 0.5*delta*delta*a[i];
+            }
+        }
+
+        let old_accelerations = world.accelerations();
+        self.closing.close(world);
+        for (i, old_acceleration) in old_accelerations.iter().enumerate() {
+            let velocity = world.bodies.velocity(i) + 0.5*delta*(old_acceleration + world.bodies.acceleration(i));
+            world.bodies.set_velocity(i, velocity);
+        }
+    }
+}
+
+// Classical fourth order Runge-Kutta. Not symplectic, but very accurate over short spans.
+pub struct RungeKutta4;
+
+impl Integrator for RungeKutta4 {
+    fn name(&self) -> &'static str {
+        "RK4"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let x0 = world.positions();
+        let v0 = world.velocities();
+
+        // Each stage is (dx/dt, dv/dt) evaluated at a trial state
+        let stage = |world: &mut World, x: &[Vector], v: &[Vector]| -> (Vec<Vector>, Vec<Vector>) {
+            world.set_positions(x);
+            world.set_velocities(v);
+            world.nbody();
+            (v.to_vec(), world.accelerations())
+        };
+        let offset = |base: &[Vector], slope: &[Vector], h: Real| -> Vec<Vector> {
+            base.iter().zip(slope).map(|(b, s)| b + h*s).collect()
+        };
+
+        let (k1x, k1v) = stage(world, &x0, &v0);
+        let (k2x, k2v) = stage(world, &offset(&x0, &k1x, 0.5*delta), &offset(&v0, &k1v, 0.5*delta));
+        let (k3x, k3v) = stage(world, &offset(&x0, &k2x, 0.5*delta), &offset(&v0, &k2v, 0.5*delta));
+        let (k4x, k4v) = stage(world, &offset(&x0, &k3x, delta), &offset(&v0, &k3v, delta));
+
+        for i in 0..world.bodies.len() {
+            world.bodies.set_position(i, x0[i] + delta/6.0*(k1x[i] + 2.0*k2x[i] + 2.0*k3x[i] + k4x[i]));
+            world.bodies.set_velocity(i, v0[i] + delta/6.0*(k1v[i] + 2.0*k2v[i] + 2.0*k3v[i] + k4v[i]));
+        }
+    }
+}
+
+// Fourth order symplectic integrator built from three leapfrog steps with weights chosen so the
+// error terms cancel (Yoshida 1990, equivalent to Forest-Ruth). One substep runs backwards.
+pub struct Yoshida4;
+
+impl Yoshida4 {
+    // w1 = 1/(2 - 2^(1/3)), w0 = -2^(1/3)/(2 - 2^(1/3))
+    const W1: f64 = 1.351_207_191_959_657_8;
+    const W0: f64 = -1.702_414_383_919_315_3;
+}
+
+impl Integrator for Yoshida4 {
+    fn name(&self) -> &'static str {
+        "Yoshida (4th order)"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let drifts = [0.5*Self::W1, 0.5*(Self::W0 + Self::W1), 0.5*(Self::W0 + Self::W1), 0.5*Self::W1];
+        let kicks = [Self::W1, Self::W0, Self::W1];
+
+        for (i, kick) in kicks.iter().enumerate() {
+            world.drift(drifts[i] as Real * delta);
+            world.nbody();
+            world.kick(*kick as Real * delta);
+        }
+        world.drift(drifts[3] as Real * delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{energy, period, two_body};
+
+    // Relative energy error after ten orbits of an e = 0.5 Kepler orbit, 200 steps per orbit
+    fn energy_error(integrator: &mut dyn Integrator) -> Real {
+        let mut world = two_body(1e-3, 0.5);
+        let initial = energy(&world);
+        let delta = period(1e-3)/200.0;
+        for _ in 0..2000 {
+            integrator.step(&mut world, delta);
+        }
+        ((energy(&world) - initial)/initial).abs() as Real
+    }
+
+    #[test]
+    fn kepler_orbit_energy() {
+        let tolerances: [(&mut dyn Integrator, Real); 5] = [
+            (&mut SemiImplicitEuler, 1e-1),
+            (&mut Leapfrog::new(), 5e-3),
+            (&mut VelocityVerlet::new(), 5e-3),
+            (&mut RungeKutta4, 1e-4),
+            (&mut Yoshida4, 1e-6),
+        ];
+        for (integrator, tolerance) in tolerances {
+            // Round-off sets a floor in single precision
+            let tolerance = tolerance.max(1e3*Real::EPSILON);
+            let error = energy_error(integrator);
+            assert!(error < tolerance, "{}: energy error {:e}", integrator.name(), error);
+        }
+    }
+
+    #[test]
+    fn leapfrog_reuses_closing_forces() {
+        let (mut reused, mut fresh) = (two_body(1e-3, 0.5), two_body(1e-3, 0.5));
+        let mut leapfrog = Leapfrog::new();
+        for _ in 0..100 {
+            leapfrog.step(&mut reused, 0.01);
+            Leapfrog::new().step(&mut fresh, 0.01);
+        }
+        assert_eq!(reused.positions(), fresh.positions());
+        assert_eq!(reused.velocities(), fresh.velocities());
+
+        // Moving a body in between steps is noticed
+        reused.bodies.set_position(1, reused.bodies.position(1)*1.01);
+        fresh.bodies.set_position(1, fresh.bodies.position(1)*1.01);
+        leapfrog.step(&mut reused, 0.01);
+        Leapfrog::new().step(&mut fresh, 0.01);
+        assert_eq!(reused.velocities(), fresh.velocities());
+
+        // So is changing what a body pulls with, or how the pull is summed
+        let edits: [fn(&mut World); 4] = [
+            |world| world.bodies.mass[0] *= 2.0,
+            |world| world.bodies.softening[0] = Some(0.1),
+            |world| world.bodies.kind[0] = Kind::Pinned(None),
+            |world| world.solver = Box::new(gravity::barnes_hut::BarnesHut::new(0.5)),
+        ];
+        for edit in edits {
+            edit(&mut reused);
+            edit(&mut fresh);
+            leapfrog.step(&mut reused, 0.01);
+            Leapfrog::new().step(&mut fresh, 0.01);
+            assert_eq!(reused.velocities(), fresh.velocities());
+        }
+    }
+}
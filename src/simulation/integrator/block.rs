@@ -0,0 +1,257 @@
+use crate::simulation::integrator::Integrator;
+use crate::simulation::{Real, Vector, World};
+
+// How each body's timestep is chosen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Criterion {
+    // dt = sqrt(2*eta*eps/|a|) with eps the body's softening length, as in GADGET. Bodies without
+    // softening have no length scale, so they fall back on `Aarseth` with the same eta.
+    Acceleration { eta: Real },
+    // dt = eta*|a|/|da/dt|, with the jerk taken by finite difference over the body's last step.
+    // The low order form of Aarseth's criterion. There is no jerk before the first step, so bodies
+    // start on the smallest step and grow from there.
+    Aarseth { eta: Real },
+}
+
+// Kick-drift-kick leapfrog with individual, power-of-two block timesteps (see `Schedule`).
+//
+// Substeps run from one step boundary to the next. Only bodies finishing a step ("active") have
+// their forces evaluated, through `World::nbody_of`, and are kicked. The rest are predicted along
+// their half-kicked velocity, which is exactly where the leapfrog drift puts them.
+pub struct BlockTimestep {
+    pub criterion: Criterion,
+    // The smallest step is delta/2^max_level
+    pub max_level: u32,
+
+    levels: Vec<u32>,
+    // Acceleration at the end of each body's last step, for the finite difference jerk
+    previous: Vec<Vector>,
+}
+
+impl BlockTimestep {
+    pub fn new(criterion: Criterion) -> Self {
+        Self {
+            criterion,
+            max_level: 16,
+            levels: vec![],
+            previous: vec![],
+        }
+    }
+
+    // Current level of every body, in body order. Empty before the first step.
+    pub fn levels(&self) -> &[u32] {
+        &self.levels
+    }
+
+    // Timestep the criterion asks for. `last` is the length of the body's last step.
+    fn timestep(&self, world: &World, i: usize, last: Real) -> Real {
+        // Nothing to resolve for bodies which forces don't move
+        if world.bodies.kind[i].is_pinned() {
+            return Real::INFINITY;
+        }
+        let acceleration = world.bodies.acceleration(i);
+        match self.criterion {
+            Criterion::Acceleration { eta } if softening_length(world, i) > 0.0 => {
+                (2.0*eta*softening_length(world, i)/acceleration.magnitude()).sqrt()
+            }
+            Criterion::Acceleration { eta } | Criterion::Aarseth { eta } => {
+                let jerk = (acceleration - self.previous[i])/last;
+                eta*acceleration.magnitude()/jerk.magnitude()
+            }
+        }
+    }
+}
+
+impl Integrator for BlockTimestep {
+    fn name(&self) -> &'static str {
+        "block timestep leapfrog"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let n = world.bodies.len();
+        if n == 0 {
+            return;
+        }
+
+        // (Re)start whenever bodies have been added or removed
+        if self.levels.len() != n {
+            world.nbody();
+            self.previous = world.accelerations();
+            self.levels = (0..n)
+                .map(|i| match self.criterion {
+                    Criterion::Acceleration { .. } if softening_length(world, i) > 0.0 => {
+                        level_for(self.timestep(world, i, delta), delta, self.max_level)
+                    }
+                    _ => self.max_level,
+                })
+                .collect();
+        }
+
+        let mut schedule = Schedule::new(&self.levels, delta, self.max_level);
+        for i in 0..n {
+            kick_body(world, i, 0.5*schedule.step_length(self.levels[i]));
+        }
+
+        while let Some((elapsed, active)) = schedule.advance() {
+            world.drift(elapsed);
+            world.nbody_of(&active);
+
+            for i in active {
+                let last = schedule.step_length(self.levels[i]);
+                kick_body(world, i, 0.5*last);
+
+                let wanted = level_for(self.timestep(world, i, last), delta, self.max_level);
+                self.levels[i] = schedule.restart(i, self.levels[i], wanted);
+                self.previous[i] = world.bodies.acceleration(i);
+
+                if !schedule.is_over() {
+                    kick_body(world, i, 0.5*schedule.step_length(self.levels[i]));
+                }
+            }
+        }
+    }
+}
+
+// Power-of-two block timesteps over one step of length delta. A body on level l steps by
+// delta/2^l, so steps nest: bodies on the same level start and finish together, and every body is
+// back in sync at the end. Time is counted in ticks of the smallest step, delta/2^max_level, so
+// that boundaries compare exactly.
+pub(super) struct Schedule {
+    ticks: u64,
+    tick: Real,
+    now: u64,
+    // Ticks at which each body's current step started and will finish
+    start: Vec<u64>,
+    end: Vec<u64>,
+}
+
+impl Schedule {
+    // Every body starts a step on its level at the beginning
+    pub(super) fn new(levels: &[u32], delta: Real, max_level: u32) -> Self {
+        let ticks: u64 = 1 << max_level;
+        Self {
+            ticks,
+            tick: delta/ticks as Real,
+            now: 0,
+            start: vec![0; levels.len()],
+            end: levels.iter().map(|level| ticks >> level).collect(),
+        }
+    }
+
+    pub(super) fn step_length(&self, level: u32) -> Real {
+        (self.ticks >> level) as Real*self.tick
+    }
+
+    pub(super) fn is_over(&self) -> bool {
+        self.now >= self.ticks
+    }
+
+    // Move on to the next boundary. Returns the time moved and the bodies finishing a step there,
+    // or None once every body has reached the end.
+    pub(super) fn advance(&mut self) -> Option<(Real, Vec<usize>)> {
+        if self.is_over() {
+            return None;
+        }
+        let next = *self.end.iter().min().unwrap();
+        let elapsed = (next - self.now) as Real*self.tick;
+        self.now = next;
+        Some((elapsed, (0..self.end.len()).filter(|i| self.end[*i] == next).collect()))
+    }
+
+    // Time since body `i` started its current step
+    pub(super) fn elapsed(&self, i: usize) -> Real {
+        (self.now - self.start[i]) as Real*self.tick
+    }
+
+    // Start the next step of body `i`, which has just finished one on level `current`, and return
+    // its level. Steps shrink to `wanted` freely, but only grow one level at a time and onto a
+    // boundary of the longer step, so they stay nested.
+    pub(super) fn restart(&mut self, i: usize, current: u32, wanted: u32) -> u32 {
+        let level = if wanted >= current || !self.now.is_multiple_of(self.ticks >> (current - 1)) {
+            wanted.max(current)
+        } else {
+            current - 1
+        };
+        self.start[i] = self.now;
+        self.end[i] = self.now + (self.ticks >> level);
+        level
+    }
+}
+
+fn softening_length(world: &World, i: usize) -> Real {
+    world.bodies.softening[i].unwrap_or(world.gravity.softening_length)
+}
+
+// Shallowest level whose step is no longer than `dt`. A `dt` of 0/0 comes from a body with
+// neither acceleration nor jerk, such as one on its own, which nothing limits the step of.
+pub(super) fn level_for(dt: Real, delta: Real, max_level: u32) -> u32 {
+    if dt.is_nan() {
+        return 0;
+    }
+    let level = (delta/dt).log2().ceil().max(0.0);
+    (level as u32).min(max_level)
+}
+
+fn kick_body(world: &mut World, i: usize, delta: Real) {
+    let velocity = world.bodies.velocity(i) + delta*world.bodies.acceleration(i);
+    world.bodies.set_velocity(i, velocity);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{energy, period, two_body};
+    use crate::simulation::Body;
+
+    // Relative energy error and final levels after ten orbits of an e = 0.5 Kepler orbit, in
+    // blocks of a twentieth of an orbit
+    fn kepler(integrator: &mut BlockTimestep, world: &mut World) -> (Real, Vec<u32>) {
+        let initial = energy(world);
+        let delta = period(1e-3)/20.0;
+        for _ in 0..200 {
+            integrator.step(world, delta);
+        }
+        (((energy(world) - initial)/initial).abs() as Real, integrator.levels().to_vec())
+    }
+
+    #[test]
+    fn acceleration_criterion_without_softening() {
+        let mut world = two_body(1e-3, 0.5);
+        let mut integrator = BlockTimestep::new(Criterion::Acceleration { eta: 0.01 });
+        let (error, levels) = kepler(&mut integrator, &mut world);
+        assert!(levels.iter().all(|level| *level < 10), "{:?}", levels);
+        assert!(error < 1e-3, "energy error {:e}", error);
+    }
+
+    #[test]
+    fn acceleration_criterion_with_softening() {
+        let mut world = two_body(1e-3, 0.5);
+        world.gravity.softening_length = 0.01;
+        let mut integrator = BlockTimestep::new(Criterion::Acceleration { eta: 0.01 });
+        let (error, levels) = kepler(&mut integrator, &mut world);
+        assert!(levels.iter().all(|level| *level < 10), "{:?}", levels);
+        assert!(error < 1e-3, "energy error {:e}", error);
+    }
+
+    #[test]
+    fn aarseth_criterion() {
+        let mut world = two_body(1e-3, 0.5);
+        let (error, levels) = kepler(&mut BlockTimestep::new(Criterion::Aarseth { eta: 0.01 }), &mut world);
+        assert!(levels.iter().all(|level| *level < 10), "{:?}", levels);
+        assert!(error < 1e-3, "energy error {:e}", error);
+    }
+
+    #[test]
+    fn isolated_body_takes_whole_steps() {
+        let mut world = World::new();
+        world.add_body(Body { velocity: Vector::new(1.0, 0.0, 0.0), ..Body::new("alone") });
+        for criterion in [Criterion::Acceleration { eta: 0.01 }, Criterion::Aarseth { eta: 0.01 }] {
+            let mut integrator = BlockTimestep::new(criterion);
+            for _ in 0..3 {
+                integrator.step(&mut world, 1.0);
+                assert_eq!(integrator.levels(), [0]);
+            }
+        }
+        assert_eq!(world.bodies.position(0), Vector::new(6.0, 0.0, 0.0));
+    }
+}
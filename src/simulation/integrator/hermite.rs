@@ -0,0 +1,217 @@
+use crate::simulation::integrator::block::{level_for, Schedule};
+use crate::simulation::integrator::{Integrator, Sources};
+use crate::simulation::{Real, Vector, World};
+
+// Fourth order Hermite predictor-corrector (Makino & Aarseth 1992), the usual scheme for
+// collisional systems. Positions and velocities are predicted from acceleration and jerk, forces
+// are evaluated at the prediction, and the result is corrected with the new acceleration and jerk.
+// Jerk comes from `World::nbody_jerk_of`, so forces are always summed directly.
+//
+// With `eta` set, bodies get power-of-two block timesteps (see `block::Schedule`) from
+// Aarseth's criterion. Bodies not finishing a step are only predicted.
+pub struct Hermite {
+    // Accuracy parameter of Aarseth's criterion, typically 0.01 to 0.03. None steps every body
+    // together by the full delta.
+    pub eta: Option<Real>,
+    // The smallest block step is delta/2^max_level
+    pub max_level: u32,
+
+    levels: Vec<u32>,
+    jerks: Vec<Vector>,
+    // The state the jerks were left for at the end of the last step
+    positions: Vec<Vector>,
+    velocities: Vec<Vector>,
+    sources: Sources,
+}
+
+impl Hermite {
+    pub fn new() -> Self {
+        Self {
+            eta: None,
+            max_level: 16,
+            levels: vec![],
+            jerks: vec![],
+            positions: vec![],
+            velocities: vec![],
+            sources: Sources::default(),
+        }
+    }
+
+    pub fn with_block_timesteps(eta: Real) -> Self {
+        Self {
+            eta: Some(eta),
+            ..Self::new()
+        }
+    }
+}
+
+impl Default for Hermite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for Hermite {
+    fn name(&self) -> &'static str {
+        match self.eta {
+            Some(_) => "Hermite (block timesteps)",
+            None => "Hermite",
+        }
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let n = world.bodies.len();
+        if n == 0 {
+            return;
+        }
+
+        // (Re)start whenever bodies have been added, removed or changed since the last step, as by
+        // a collision or an edit. The first steps are chosen from dt = eta*|a|/|j| as there are no
+        // higher derivatives yet.
+        let changed = self.positions != world.positions()
+            || self.velocities != world.velocities()
+            || self.sources != Sources::of(world);
+        if changed {
+            let all: Vec<usize> = (0..n).collect();
+            self.jerks = world.nbody_jerk_of(&all);
+            self.levels = (0..n)
+                .map(|i| match self.eta {
+                    Some(_) if world.bodies.kind[i].is_pinned() => 0,
+                    Some(eta) => {
+                        let dt = eta*world.bodies.acceleration(i).magnitude()/self.jerks[i].magnitude();
+                        level_for(dt, delta, self.max_level)
+                    }
+                    None => 0,
+                })
+                .collect();
+        }
+        if self.eta.is_none() {
+            self.levels.iter_mut().for_each(|level| *level = 0);
+        }
+
+        // State of every body at the start of its current step
+        let mut x0 = world.positions();
+        let mut v0 = world.velocities();
+        let mut a0 = world.accelerations();
+
+        let mut schedule = Schedule::new(&self.levels, delta, self.max_level);
+        while let Some((_, active)) = schedule.advance() {
+            // Predict everyone to now
+            for i in 0..n {
+                let dt = schedule.elapsed(i);
+                let j = self.jerks[i];
+                world.bodies.set_position(i, x0[i] + dt*(v0[i] + dt*(a0[i]/2.0 + dt*j/6.0)));
+                world.bodies.set_velocity(i, v0[i] + dt*(a0[i] + dt*j/2.0));
+            }
+
+            let jerks = world.nbody_jerk_of(&active);
+
+            for (i, j1) in active.into_iter().zip(jerks) {
+                let dt = schedule.elapsed(i);
+                let (a1, j0) = (world.bodies.acceleration(i), self.jerks[i]);
+
+                let v1 = v0[i] + dt/2.0*(a0[i] + a1) + dt*dt/12.0*(j0 - j1);
+                let x1 = x0[i] + dt/2.0*(v0[i] + v1) + dt*dt/12.0*(a0[i] - a1);
+                world.bodies.set_position(i, x1);
+                world.bodies.set_velocity(i, v1);
+
+                let mut wanted = self.levels[i];
+                if let Some(eta) = self.eta {
+                    // Second and third derivatives of the acceleration at the end of the step,
+                    // from the Hermite interpolant
+                    let a3 = (12.0*(a0[i] - a1) + 6.0*dt*(j0 + j1))/(dt*dt*dt);
+                    let a2 = (-6.0*(a0[i] - a1) - dt*(4.0*j0 + 2.0*j1))/(dt*dt) + dt*a3;
+
+                    let (a1, j1, a2, a3) = (a1.magnitude(), j1.magnitude(), a2.magnitude(), a3.magnitude());
+                    let dt = if world.bodies.kind[i].is_pinned() {
+                        Real::INFINITY
+                    } else {
+                        (eta*(a1*a2 + j1*j1)/(j1*a3 + a2*a2)).sqrt()
+                    };
+                    wanted = level_for(dt, delta, self.max_level);
+                }
+                self.levels[i] = schedule.restart(i, self.levels[i], wanted);
+
+                x0[i] = x1;
+                v0[i] = v1;
+                a0[i] = a1;
+                self.jerks[i] = j1;
+            }
+        }
+
+        self.positions = x0;
+        self.velocities = v0;
+        self.sources = Sources::of(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::{energy, period, two_body};
+    use crate::simulation::Body;
+
+    // Relative energy error after ten orbits of an e = 0.5 Kepler orbit in `steps` per orbit
+    fn energy_error(integrator: &mut Hermite, steps: usize) -> Real {
+        let mut world = two_body(1e-3, 0.5);
+        let initial = energy(&world);
+        let delta = period(1e-3)/steps as Real;
+        for _ in 0..10*steps {
+            integrator.step(&mut world, delta);
+        }
+        ((energy(&world) - initial)/initial).abs() as Real
+    }
+
+    #[test]
+    fn fourth_order() {
+        let coarse = energy_error(&mut Hermite::new(), 200);
+        let fine = energy_error(&mut Hermite::new(), 400);
+        assert!(coarse < 1e-4, "energy error {:e}", coarse);
+        // Halving the step cuts the error at least 2^4 times. Round-off sets a floor in single
+        // precision.
+        assert!(fine < (coarse/16.0).max(1e2*Real::EPSILON), "{:e} then {:e}", coarse, fine);
+    }
+
+    #[test]
+    fn block_timesteps() {
+        let mut integrator = Hermite::with_block_timesteps(0.02);
+        let error = energy_error(&mut integrator, 10);
+        assert!(error < 1e-4, "energy error {:e}", error);
+        assert!(integrator.levels.iter().all(|level| *level > 0 && *level < 10), "{:?}", integrator.levels);
+    }
+
+    #[test]
+    fn isolated_body_takes_whole_steps() {
+        let mut world = World::new();
+        world.add_body(Body { velocity: Vector::new(1.0, 0.0, 0.0), ..Body::new("alone") });
+        let mut integrator = Hermite::with_block_timesteps(0.02);
+        for _ in 0..3 {
+            integrator.step(&mut world, 1.0);
+            assert_eq!(integrator.levels, [0]);
+        }
+        assert_eq!(world.bodies.position(0), Vector::new(3.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn edits_between_steps_restart() {
+        let (mut kept, mut fresh) = (two_body(1e-3, 0.5), two_body(1e-3, 0.5));
+        let mut integrator = Hermite::with_block_timesteps(0.02);
+        let delta = period(1e-3)/10.0;
+        integrator.step(&mut kept, delta);
+        Hermite::with_block_timesteps(0.02).step(&mut fresh, delta);
+
+        let edits: [fn(&mut World); 3] = [
+            |world| world.bodies.set_velocity(1, -world.bodies.velocity(1)),
+            |world| world.bodies.mass[0] *= 2.0,
+            |world| world.bodies.set_position(1, world.bodies.position(1)*1.01),
+        ];
+        for edit in edits {
+            edit(&mut kept);
+            edit(&mut fresh);
+            integrator.step(&mut kept, delta);
+            Hermite::with_block_timesteps(0.02).step(&mut fresh, delta);
+            assert_eq!(kept.positions(), fresh.positions());
+            assert_eq!(kept.velocities(), fresh.velocities());
+        }
+    }
+}
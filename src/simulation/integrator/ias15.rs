@@ -0,0 +1,299 @@
+use crate::simulation::integrator::Integrator;
+use crate::simulation::{Real, Vector, World};
+
+// Gauss-Radau spacings: the nodes of 8 point Radau quadrature on [0, 1], excluding 0
+const NODES: [f64; 7] = [
+    0.05626256053692215,
+    0.18024069173689236,
+    0.3526247171131696,
+    0.5471536263305554,
+    0.7342101772154105,
+    0.8853209468390958,
+    0.9775206135612875,
+];
+
+// A step is retried when it would have wanted to be this much shorter
+const SAFETY_FACTOR: Real = 0.25;
+const MAX_ITERATIONS: usize = 12;
+
+// 15th order Gauss-Radau integrator with adaptive steps, after IAS15 (Rein & Spiegel 2015).
+// Within a step the acceleration is fitted by a 7th degree polynomial in time through its values
+// at the Radau nodes, iterated to convergence, and positions and velocities are integrated from
+// the polynomial exactly. The size of the highest order term sets the next step, so `step` takes
+// as many internal steps as it needs to cover `delta`, keeping round-off errors at machine
+// precision. Meant for few-body problems with exact forces; use it with `DirectSum`.
+pub struct Ias15 {
+    // Target relative size of the last term of the acceleration polynomial. Round-off alone
+    // keeps that term at thousands of times machine epsilon, and asking for less shrinks the step
+    // without end, so the default is 1e-9 only in double precision.
+    pub epsilon: Real,
+
+    // Length of the next internal step
+    dt: Real,
+    // Per body: Newton (g) and power series (b) coefficients of the acceleration polynomial, and
+    // compensated summation remainders for position and velocity. The coefficients are in
+    // fractions of a step of length `b_dt`.
+    g: Vec<[Vector; 7]>,
+    b: Vec<[Vector; 7]>,
+    b_dt: Real,
+    compensation_x: Vec<Vector>,
+    compensation_v: Vec<Vector>,
+
+    // coefficients[k][m]: coefficient of h^(m+1) in h*(h - h_1)*...*(h - h_k), the Newton basis
+    coefficients: [[Real; 7]; 7],
+}
+
+impl Ias15 {
+    pub fn new() -> Self {
+        let mut coefficients = [[0.0; 7]; 7];
+        for k in 0..7 {
+            // Multiply out one root at a time, starting from h
+            let mut polynomial = [0.0; 8];
+            polynomial[1] = 1.0;
+            for node in &NODES[..k] {
+                for m in (1..8).rev() {
+                    polynomial[m] = polynomial[m - 1] - node*polynomial[m];
+                }
+                polynomial[0] *= -node;
+            }
+            for m in 0..7 {
+                coefficients[k][m] = polynomial[m + 1] as Real;
+            }
+        }
+
+        Self {
+            epsilon: (1e4*Real::EPSILON).max(1e-9),
+            dt: 0.0,
+            g: vec![],
+            b: vec![],
+            b_dt: 0.0,
+            compensation_x: vec![],
+            compensation_v: vec![],
+            coefficients,
+        }
+    }
+
+    // Length of the next internal step, 0 before the first
+    pub fn timestep(&self) -> Real {
+        self.dt
+    }
+
+    // Position and velocity after a fraction `h` of a step of length `dt`
+    fn predict(&self, i: usize, x0: Vector, v0: Vector, a0: Vector, h: Real, dt: Real) -> (Vector, Vector) {
+        let b = &self.b[i];
+        let mut x = a0/2.0;
+        let mut v = a0;
+        let mut power = h;
+        for (m, b) in b.iter().enumerate() {
+            let m = m as Real;
+            x += b*power/((m + 2.0)*(m + 3.0));
+            v += b*power/(m + 2.0);
+            power *= h;
+        }
+        (x0 + dt*h*(v0 + dt*h*x), v0 + dt*h*v)
+    }
+
+    // Express the polynomial in fractions of a step of length `dt`, then recompute the Newton
+    // coefficients from it, inverting `coefficients` from the top down. A polynomial from a much
+    // shorter step is mostly round-off once stretched, so it is dropped instead.
+    fn rescale(&mut self, dt: Real) {
+        let ratio = dt/self.b_dt;
+        if self.b_dt == 0.0 || ratio > 1.0/SAFETY_FACTOR {
+            self.b.iter_mut().for_each(|b| *b = [Vector::zeros(); 7]);
+        } else if ratio != 1.0 {
+            for b in &mut self.b {
+                let mut power = ratio;
+                for term in b.iter_mut() {
+                    *term *= power;
+                    power *= ratio;
+                }
+            }
+        }
+        self.b_dt = dt;
+
+        for (g, b) in self.g.iter_mut().zip(&self.b) {
+            for m in (0..7).rev() {
+                g[m] = b[m];
+                for k in m + 1..7 {
+                    g[m] -= g[k]*self.coefficients[k][m];
+                }
+            }
+        }
+    }
+
+    // Try one step of length `dt` from the current state. Returns the ideal length of the next
+    // step if it was accepted; otherwise the state is restored and the shorter step to retry with
+    // is left in `self.dt`.
+    fn attempt(&mut self, world: &mut World, dt: Real) -> Option<Real> {
+        let n = world.bodies.len();
+        let x0 = world.positions();
+        let v0 = world.velocities();
+        world.nbody();
+        let a0 = world.accelerations();
+        self.rescale(dt);
+
+        // Predictor-corrector: refit the polynomial at every node until it stops changing
+        let scale = a0.iter().map(|a| a.amax()).fold(0.0, Real::max);
+        let mut last_error = Real::INFINITY;
+        for iteration in 0..MAX_ITERATIONS {
+            let previous: Vec<Vector> = self.b.iter().map(|b| b[6]).collect();
+
+            for (node, h) in NODES.iter().enumerate() {
+                let h = *h as Real;
+                for i in 0..n {
+                    let (x, v) = self.predict(i, x0[i], v0[i], a0[i], h, dt);
+                    world.bodies.set_position(i, x);
+                    world.bodies.set_velocity(i, v);
+                }
+                world.nbody();
+
+                for (i, a0) in a0.iter().enumerate() {
+                    // Divided difference through this node, given the earlier ones
+                    let mut g = (world.bodies.acceleration(i) - a0)/h;
+                    for (k, other) in NODES[..node].iter().enumerate() {
+                        g = (g - self.g[i][k])/(h - *other as Real);
+                    }
+                    let change = g - self.g[i][node];
+                    self.g[i][node] = g;
+                    for m in 0..=node {
+                        self.b[i][m] += change*self.coefficients[node][m];
+                    }
+                }
+            }
+
+            let change = self.b.iter().zip(&previous)
+                .map(|(b, previous)| (b[6] - previous).amax())
+                .fold(0.0, Real::max);
+            let error = if scale == 0.0 { 0.0 } else { change/scale };
+            // Stop once converged, or once round-off stops it improving
+            if error < Real::EPSILON || (iteration > 1 && error >= last_error) {
+                break;
+            }
+            last_error = error;
+        }
+
+        // Ideal step from the size of the last term, relative to the accelerations
+        let last_term = self.b.iter().map(|b| b[6].amax()).fold(0.0, Real::max);
+        let ratio = if last_term == 0.0 || scale == 0.0 {
+            Real::INFINITY
+        } else {
+            (self.epsilon/(last_term/scale)).powf(1.0/7.0)
+        };
+
+        if ratio < SAFETY_FACTOR {
+            // Far too long: go back and try again
+            world.set_positions(&x0);
+            world.set_velocities(&v0);
+            for (i, acceleration) in a0.iter().enumerate() {
+                world.bodies.set_acceleration(i, *acceleration);
+            }
+            self.dt = dt*ratio;
+            return None;
+        }
+
+        // Accept, summing the increments with compensation so round-off stays random
+        for i in 0..n {
+            let (x, v) = self.predict(i, Vector::zeros(), v0[i], a0[i], 1.0, dt);
+            world.bodies.set_position(i, compensated_add(x0[i], x, &mut self.compensation_x[i]));
+            world.bodies.set_velocity(i, compensated_add(v0[i], v - v0[i], &mut self.compensation_v[i]));
+        }
+
+        // Carry the polynomial over as the guess for the next step: a(1 + s) in powers of s
+        for b in &mut self.b {
+            let old = *b;
+            for (m, term) in b.iter_mut().enumerate() {
+                *term = Vector::zeros();
+                let mut binomial = 1.0;
+                for (k, old) in old.iter().enumerate().skip(m) {
+                    // binomial = C(k + 1, m + 1)
+                    if k > m {
+                        binomial *= (k + 1) as Real/(k - m) as Real;
+                    }
+                    *term += old*binomial;
+                }
+            }
+        }
+        Some(dt*ratio)
+    }
+}
+
+impl Default for Ias15 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for Ias15 {
+    fn name(&self) -> &'static str {
+        "IAS15"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let n = world.bodies.len();
+        if self.b.len() != n {
+            self.g = vec![[Vector::zeros(); 7]; n];
+            self.b = vec![[Vector::zeros(); 7]; n];
+            self.b_dt = 0.0;
+            self.compensation_x = vec![Vector::zeros(); n];
+            self.compensation_v = vec![Vector::zeros(); n];
+        }
+        if self.dt <= 0.0 {
+            self.dt = delta;
+        }
+
+        // Internal steps until delta is covered, shortening the last one to land on it exactly.
+        // Steps grow by at most 1/SAFETY_FACTOR at a time. A shortened step keeps the current
+        // step length: its estimate is dominated by round-off when it is much shorter.
+        let mut elapsed = 0.0;
+        loop {
+            let remaining = delta - elapsed;
+            let shortened = self.dt > remaining;
+            let dt = if shortened { remaining } else { self.dt };
+            if let Some(ideal) = self.attempt(world, dt) {
+                if !shortened {
+                    self.dt = ideal.min(dt/SAFETY_FACTOR);
+                }
+                elapsed += dt;
+                if shortened || elapsed >= delta {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// sum + increment, carrying the low order bits lost to rounding in `compensation` (Kahan)
+fn compensated_add(sum: Vector, increment: Vector, compensation: &mut Vector) -> Vector {
+    let y = increment - *compensation;
+    let t = sum + y;
+    *compensation = (t - sum) - y;
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::kepler;
+    use crate::simulation::tests::{energy, period, relative, two_body};
+
+    #[test]
+    fn eccentric_kepler_orbit() {
+        let mut world = two_body(1e-3, 0.9);
+        let (position, velocity) = relative(&world);
+        let initial = energy(&world);
+
+        // Ten orbits, in steps of a tenth so that every one starts somewhere different
+        let mut integrator = Ias15::new();
+        let delta = period(1e-3)/10.0;
+        for _ in 0..100 {
+            integrator.step(&mut world, delta);
+        }
+
+        let (expected, _) = kepler::propagate(position, velocity, 1.0 + 1e-3, 100.0*delta);
+        let (actual, _) = relative(&world);
+        let error = ((energy(&world) - initial)/initial).abs() as Real;
+        // Down to round-off
+        assert!((actual - expected).magnitude() < 1e5*Real::EPSILON, "{:?} instead of {:?}", actual, expected);
+        assert!(error < 1e3*Real::EPSILON, "energy error {:e}", error);
+    }
+}
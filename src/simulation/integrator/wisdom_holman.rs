@@ -0,0 +1,220 @@
+use crate::simulation::integrator::Integrator;
+use crate::simulation::{kepler, Real, Vector, World};
+
+// Wisdom-Holman symplectic map in democratic heliocentric coordinates (Duncan, Levison & Lee
+// 1998), for planetary systems with one dominant body. Positions are taken relative to the most
+// massive body that isn't pinned, and velocities relative to the barycentre. Each orbiter's
+// Kepler orbit about the central body is followed exactly, so only the much weaker pull between
+// orbiters limits the step, which can then be a sizeable fraction of the shortest orbit.
+//
+// The step is kick (orbiter interactions), jump (central body's recoil), Kepler drift, jump,
+// kick. Test particles are orbiters without mass. Pinned bodies are outside perturbers: their
+// pull on every other body, the central one included, is applied in the kicks, and they drift
+// along their velocity as with the other integrators.
+//
+// Gravity is summed here rather than through `World::nbody`, so the world's solver,
+// post-Newtonian terms and extra forces are all left out, and regularised pairs only keep an
+// approximately right centre of mass.
+pub struct WisdomHolman;
+
+impl WisdomHolman {
+    // Velocity change of every orbiter over `delta` from the other orbiters and the perturbers.
+    // Positions are relative to the central body. Returns the change in the barycentre's velocity,
+    // which only the perturbers can cause; `velocities` stay relative to the barycentre.
+    fn kick(
+        world: &World,
+        central: usize,
+        orbiters: &[usize],
+        positions: &[Vector],
+        velocities: &mut [Vector],
+        perturbers: &[(usize, Vector)],
+        delta: Real,
+    ) -> Vector {
+        let (bodies, gravity) = (&world.bodies, &world.gravity);
+        let pull = |i: usize, position: Vector, sources: &mut dyn Iterator<Item = (usize, Vector)>| {
+            let mut acceleration = Vector::zeros();
+            for (j, source) in sources {
+                if j != i && bodies.mass[j] != 0.0 {
+                    let epsilon = gravity.pair_softening(bodies.softening[i], bodies.softening[j]);
+                    acceleration += gravity.acceleration(source - position, bodies.mass[j], epsilon);
+                }
+            }
+            acceleration
+        };
+
+        let mut total_mass = bodies.mass[central];
+        let mut external_force = bodies.mass[central]
+            *pull(central, Vector::zeros(), &mut perturbers.iter().copied());
+        for (a, i) in orbiters.iter().enumerate() {
+            let internal = pull(*i, positions[a], &mut orbiters.iter().copied().zip(positions.iter().copied()));
+            let external = pull(*i, positions[a], &mut perturbers.iter().copied());
+            velocities[a] += delta*(internal + external);
+            total_mass += bodies.mass[*i];
+            external_force += bodies.mass[*i]*external;
+        }
+
+        let barycentre_kick = delta*external_force/total_mass;
+        velocities.iter_mut().for_each(|velocity| *velocity -= barycentre_kick);
+        barycentre_kick
+    }
+}
+
+impl Integrator for WisdomHolman {
+    fn name(&self) -> &'static str {
+        "Wisdom-Holman"
+    }
+
+    fn step(&mut self, world: &mut World, delta: Real) {
+        let bodies = &world.bodies;
+        let Some(central) = (0..bodies.len())
+            .filter(|i| !bodies.kind[*i].is_pinned())
+            .max_by(|a, b| bodies.mass[*a].total_cmp(&bodies.mass[*b])) else {
+            return;
+        };
+        let orbiters: Vec<usize> = (0..bodies.len())
+            .filter(|i| *i != central && !bodies.kind[*i].is_pinned())
+            .collect();
+        let pinned: Vec<usize> = (0..bodies.len())
+            .filter(|i| bodies.kind[*i].is_pinned())
+            .collect();
+        let central_mass = bodies.mass[central];
+        let mu = world.gravity.g*central_mass;
+
+        // Barycentre of the central body and orbiters
+        let total_mass: Real = orbiters.iter().map(|i| bodies.mass[*i]).sum::<Real>() + central_mass;
+        let mut barycentre = central_mass*bodies.position(central);
+        let mut barycentre_velocity = central_mass*bodies.velocity(central);
+        for i in &orbiters {
+            barycentre += bodies.mass[*i]*bodies.position(*i);
+            barycentre_velocity += bodies.mass[*i]*bodies.velocity(*i);
+        }
+        barycentre /= total_mass;
+        barycentre_velocity /= total_mass;
+
+        // Heliocentric positions, barycentric velocities
+        let mut positions: Vec<Vector> = orbiters.iter()
+            .map(|i| bodies.position(*i) - bodies.position(central))
+            .collect();
+        let mut velocities: Vec<Vector> = orbiters.iter()
+            .map(|i| bodies.velocity(*i) - barycentre_velocity)
+            .collect();
+        // Pinned bodies, with positions also relative to the central body
+        let perturbers: Vec<(usize, Vector)> = pinned.iter()
+            .map(|i| (*i, bodies.position(*i) - bodies.position(central)))
+            .collect();
+
+        // Shift of every orbiter from the central body's motion
+        let jump = |positions: &mut Vec<Vector>, velocities: &Vec<Vector>, delta: Real| {
+            let momentum: Vector = orbiters.iter().zip(velocities).map(|(i, v)| bodies.mass[*i]*v).sum();
+            let shift = delta*momentum/central_mass;
+            positions.iter_mut().for_each(|position| *position += shift);
+        };
+
+        barycentre_velocity += Self::kick(
+            world, central, &orbiters, &positions, &mut velocities, &perturbers, 0.5*delta,
+        );
+        jump(&mut positions, &velocities, 0.5*delta);
+        for (position, velocity) in positions.iter_mut().zip(&mut velocities) {
+            (*position, *velocity) = kepler::propagate(*position, *velocity, mu, delta);
+        }
+        jump(&mut positions, &velocities, 0.5*delta);
+
+        // Back to the world frame. The barycentre moves uniformly between kicks, and where the
+        // central body ends up only depends on the orbiters' positions.
+        barycentre += delta*barycentre_velocity;
+        let mut central_position = barycentre;
+        for (a, i) in orbiters.iter().enumerate() {
+            central_position -= bodies.mass[*i]*positions[a]/total_mass;
+        }
+        let pinned_ends: Vec<Vector> = pinned.iter()
+            .map(|i| bodies.position(*i) + delta*bodies.velocity(*i))
+            .collect();
+        let perturbers: Vec<(usize, Vector)> = pinned.iter().zip(&pinned_ends)
+            .map(|(i, position)| (*i, position - central_position))
+            .collect();
+
+        barycentre_velocity += Self::kick(
+            world, central, &orbiters, &positions, &mut velocities, &perturbers, 0.5*delta,
+        );
+
+        let mut central_velocity = barycentre_velocity;
+        for (a, i) in orbiters.iter().enumerate() {
+            central_velocity -= bodies.mass[*i]*velocities[a]/central_mass;
+        }
+
+        let bodies = &mut world.bodies;
+        bodies.set_position(central, central_position);
+        bodies.set_velocity(central, central_velocity);
+        for (a, i) in orbiters.iter().enumerate() {
+            bodies.set_position(*i, central_position + positions[a]);
+            bodies.set_velocity(*i, barycentre_velocity + velocities[a]);
+        }
+        for (i, position) in pinned.iter().zip(pinned_ends) {
+            bodies.set_position(*i, position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+    use crate::simulation::integrator::Leapfrog;
+    use crate::simulation::tests::{energy, period, relative, two_body};
+    use crate::simulation::{Body, MasslessFrame};
+
+    #[test]
+    fn test_particle_follows_kepler() {
+        let mut world = two_body(0.0, 0.5);
+        let (position, velocity) = relative(&world);
+        let delta = period(0.0)/7.0;
+        for _ in 0..70 {
+            WisdomHolman.step(&mut world, delta);
+        }
+
+        let (expected, _) = kepler::propagate(position, velocity, 1.0, 70.0*delta);
+        let (actual, _) = relative(&world);
+        assert!((actual - expected).magnitude() < 1e4*Real::EPSILON, "{:?} instead of {:?}", actual, expected);
+    }
+
+    // Sun with a Jupiter and a Saturn, at about their real mass and distance ratios
+    fn planets() -> World {
+        let mut world = World::new();
+        world.add_body(Body::new("sun"));
+        for (name, mass, semi_major_axis) in [("jupiter", 1e-3, 1.0), ("saturn", 3e-4, 1.83)] {
+            let mut planet = Body::new(name);
+            planet.mass = mass;
+            let elements = Elements {
+                semi_major_axis,
+                eccentricity: 0.05,
+                inclination: 0.02,
+                longitude_of_ascending_node: mass*1e3,
+                argument_of_periapsis: 1.0,
+                true_anomaly: semi_major_axis,
+            };
+            world.add_body_from_elements(planet, "sun", &elements);
+        }
+        world.shift_to_barycentre(MasslessFrame::Shift);
+        world
+    }
+
+    #[test]
+    fn long_steps_keep_energy() {
+        // A twentieth of the inner orbit for a hundred orbits
+        let delta = period(0.0)/20.0;
+        let error = |integrator: &mut dyn Integrator| {
+            let mut world = planets();
+            let initial = energy(&world);
+            for _ in 0..2000 {
+                integrator.step(&mut world, delta);
+            }
+            ((energy(&world) - initial)/initial).abs() as Real
+        };
+
+        let wisdom_holman = error(&mut WisdomHolman);
+        let leapfrog = error(&mut Leapfrog::new());
+        // Round-off sets a floor in single precision
+        assert!(wisdom_holman < (1e3*Real::EPSILON).max(1e-5), "energy error {:e}", wisdom_holman);
+        assert!(wisdom_holman < leapfrog/20.0, "{:e} against {:e} for leapfrog", wisdom_holman, leapfrog);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::simulation::{Real, Vector};
+
+// Analytic two-body propagation. `position` and `velocity` are relative to the primary and `mu`
+// is G*(m1 + m2) (or G*M for a test particle).
+//
+// Uses universal variables, so elliptic, parabolic and hyperbolic orbits all go through the same
+// equations: Kepler's equation is solved for the universal anomaly chi with the Laguerre-Conway
+// iteration, which converges from a rough first guess even for long, eccentric steps, and the
+// state is advanced with Gauss' f and g functions.
+pub fn propagate(position: Vector, velocity: Vector, mu: Real, delta: Real) -> (Vector, Vector) {
+    let r0 = position.magnitude();
+    if delta == 0.0 || r0 == 0.0 || mu == 0.0 {
+        return (position + delta*velocity, velocity);
+    }
+
+    let sqrt_mu = mu.sqrt();
+    // alpha = 1/a: positive for ellipses, zero for parabolas, negative for hyperbolas
+    let alpha = 2.0/r0 - velocity.magnitude_squared()/mu;
+    let sigma0 = position.dot(&velocity)/sqrt_mu;
+
+    // Whole periods change nothing, and would make chi needlessly large
+    let mut delta_reduced = delta;
+    if alpha > 0.0 {
+        let period = 2.0*std::f64::consts::PI as Real/(sqrt_mu*alpha*alpha.sqrt());
+        delta_reduced = delta%period;
+    }
+
+    // Universal Kepler's equation F(chi) = 0 and its first two derivatives. F' is the radius.
+    let kepler = |chi: Real| {
+        let z = alpha*chi*chi;
+        let (c, s) = stumpff(z);
+        let chi2 = chi*chi;
+        let f = sigma0*chi2*c + (1.0 - alpha*r0)*chi2*chi*s + r0*chi - sqrt_mu*delta_reduced;
+        let df = sigma0*chi*(1.0 - z*s) + (1.0 - alpha*r0)*chi2*c + r0;
+        let ddf = sigma0*(1.0 - z*c) + (1.0 - alpha*r0)*chi*(1.0 - z*s);
+        (f, df, ddf)
+    };
+
+    // First guess: mean motion for ellipses, asymptotic motion for hyperbolas (Vallado), and the
+    // radial solution if neither applies
+    let mut chi = sqrt_mu*delta_reduced/r0;
+    if alpha > 0.0 {
+        chi = sqrt_mu*delta_reduced*alpha;
+    } else if alpha < 0.0 {
+        let a = 1.0/alpha;
+        let sign = delta_reduced.signum();
+        let argument = -2.0*mu*alpha*delta_reduced
+            /(position.dot(&velocity) + sign*(-mu*a).sqrt()*(1.0 - r0*alpha));
+        let guess = sign*(-a).sqrt()*argument.ln();
+        if guess.is_finite() {
+            chi = guess;
+        }
+    }
+
+    // Laguerre-Conway with n = 5
+    const N: Real = 5.0;
+    for _ in 0..64 {
+        let (f, df, ddf) = kepler(chi);
+        let root = ((N - 1.0)*(N - 1.0)*df*df - N*(N - 1.0)*f*ddf).abs().sqrt();
+        let denominator = if df >= 0.0 { df + root } else { df - root };
+        if denominator == 0.0 {
+            break;
+        }
+        let step = N*f/denominator;
+        chi -= step;
+        if step.abs() <= Real::EPSILON*chi.abs().max(1.0) {
+            break;
+        }
+    }
+
+    let z = alpha*chi*chi;
+    let (c, s) = stumpff(z);
+    let chi2 = chi*chi;
+    let r = sigma0*chi*(1.0 - z*s) + (1.0 - alpha*r0)*chi2*c + r0;
+
+    let f = 1.0 - chi2/r0*c;
+    let g = delta_reduced - chi2*chi/sqrt_mu*s;
+    let f_dot = sqrt_mu/(r*r0)*chi*(z*s - 1.0);
+    let g_dot = 1.0 - chi2/r*c;
+
+    (f*position + g*velocity, f_dot*position + g_dot*velocity)
+}
+
+// Stumpff functions C(z) and S(z). Near z = 0 the closed forms cancel badly, so the series is
+// summed instead.
+fn stumpff(z: Real) -> (Real, Real) {
+    if z.abs() < 1.0 {
+        // C = sum of (-z)^k/(2k + 2)!, S = sum of (-z)^k/(2k + 3)!
+        let mut c: Real = 0.0;
+        let mut s: Real = 0.0;
+        let mut term_c: Real = 0.5;
+        let mut term_s: Real = 1.0/6.0;
+        let mut k = 0.0;
+        while term_c.abs() > Real::EPSILON*c.abs() || term_s.abs() > Real::EPSILON*s.abs() {
+            c += term_c;
+            s += term_s;
+            term_c *= -z/((2.0*k + 3.0)*(2.0*k + 4.0));
+            term_s *= -z/((2.0*k + 4.0)*(2.0*k + 5.0));
+            k += 1.0;
+        }
+        (c, s)
+    } else if z > 0.0 {
+        let root = z.sqrt();
+        ((1.0 - root.cos())/z, (root - root.sin())/(z*root))
+    } else {
+        let root = (-z).sqrt();
+        ((root.cosh() - 1.0)/(-z), (root.sinh() - root)/(-z*root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+
+    const PI: Real = std::f64::consts::PI as Real;
+
+    fn orbit(semi_major_axis: Real, eccentricity: Real) -> Elements {
+        Elements {
+            semi_major_axis,
+            eccentricity,
+            inclination: 0.4,
+            longitude_of_ascending_node: 1.0,
+            argument_of_periapsis: 2.0,
+            true_anomaly: 0.3,
+        }
+    }
+
+    #[test]
+    fn mean_anomaly_advances_uniformly() {
+        let mu = 3.0;
+        for (semi_major_axis, eccentricity) in [(2.0, 0.01), (2.0, 0.5), (2.0, 0.95), (-2.0, 1.5)] {
+            let elements = orbit(semi_major_axis, eccentricity);
+            let (position, velocity) = elements.to_state(mu);
+            let mean_motion = (mu/(semi_major_axis*semi_major_axis*semi_major_axis).abs()).sqrt();
+
+            for delta in [0.3, 5.0, -2.0, 100.0] {
+                let (position, velocity) = propagate(position, velocity, mu, delta);
+                let mean = Elements::from_state(position, velocity, mu).mean_anomaly();
+                let expected = elements.mean_anomaly() + mean_motion*delta;
+                let mut error = mean - expected;
+                if eccentricity < 1.0 {
+                    error = (error + PI).rem_euclid(2.0*PI) - PI;
+                }
+                assert!(error.abs() < 1e4*Real::EPSILON*expected.abs().max(1.0), "a = {} e = {} after {}: {:e}", semi_major_axis, eccentricity, delta, error);
+            }
+        }
+    }
+
+    #[test]
+    fn forward_and_back() {
+        // Elliptic, parabolic and hyperbolic, from pericentre inwards and outwards
+        let mu = 1.0;
+        let position = Vector::new(1.0, 0.2, 0.0);
+        for speed in [0.8, 2.0/position.magnitude().sqrt(), 2.5] {
+            for direction in [Vector::new(-0.3, 1.0, 0.1), Vector::new(0.3, 1.0, 0.1)] {
+                let velocity = speed*direction.normalize();
+                let (x, v) = propagate(position, velocity, mu, 7.0);
+
+                let energy = |x: Vector, v: Vector| 0.5*v.magnitude_squared() - mu/x.magnitude();
+                assert!((energy(x, v) - energy(position, velocity)).abs() < 1e2*Real::EPSILON);
+                assert!((x.cross(&v) - position.cross(&velocity)).magnitude() < 1e2*Real::EPSILON);
+
+                let (x, v) = propagate(x, v, mu, -7.0);
+                assert!((x - position).magnitude() < 1e4*Real::EPSILON, "{:?} instead of {:?}", x, position);
+                assert!((v - velocity).magnitude() < 1e4*Real::EPSILON, "{:?} instead of {:?}", v, velocity);
+            }
+        }
+    }
+
+    #[test]
+    fn straight_line_without_gravity() {
+        let (position, velocity) = (Vector::new(1.0, 2.0, 3.0), Vector::new(0.5, 0.0, -1.0));
+        assert_eq!(propagate(position, velocity, 0.0, 2.0), (position + 2.0*velocity, velocity));
+    }
+}
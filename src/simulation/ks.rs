@@ -0,0 +1,134 @@
+use nalgebra as na;
+
+use crate::simulation::{kepler, Real, Vector};
+
+type Vector4 = na::Vector4<Real>;
+
+// Bound two-body propagation in Kustaanheimo-Stiefel coordinates. `position` and `velocity` are
+// relative and `mu` is G*(m1 + m2). Unbound orbits are handed to `kepler::propagate`.
+//
+// KS maps the relative position to a 4-vector u with r = |u|^2 and replaces time with s, where
+// dt = r ds. The Kepler problem then becomes a harmonic oscillator in u with frequency
+// sqrt(-h/2), h being the orbital energy per unit reduced mass, which is regular even at r = 0.
+// The oscillator is solved in closed form and the s giving the step `delta` found by Newton's
+// method, so close approaches cost nothing extra however eccentric the orbit is.
+pub fn propagate(position: Vector, velocity: Vector, mu: Real, delta: Real) -> (Vector, Vector) {
+    let r = position.magnitude();
+    if delta == 0.0 || r == 0.0 {
+        return (position + delta*velocity, velocity);
+    }
+    let (u0, du0) = to_ks(position, velocity);
+    let h = (2.0*du0.magnitude_squared() - mu)/r;
+    if h >= 0.0 {
+        return kepler::propagate(position, velocity, mu, delta);
+    }
+    let omega = (-0.5*h).sqrt();
+
+    // u(s) = a cos(omega s) + b sin(omega s). Physical time is the integral of |u|^2:
+    // t(s) = mean*s + p*sin(2 omega s) + q*(1 - cos(2 omega s))
+    let (a, b) = (u0, du0/omega);
+    let mean = 0.5*(a.magnitude_squared() + b.magnitude_squared());
+    let p = (a.magnitude_squared() - b.magnitude_squared())/(4.0*omega);
+    let q = a.dot(&b)/(2.0*omega);
+    let time = |s: Real| {
+        let (sin, cos) = (2.0*omega*s).sin_cos();
+        (mean*s + p*sin + q*(1.0 - cos), mean + 2.0*omega*(p*cos + q*sin))
+    };
+
+    // The periodic part is bounded, which brackets the root. Newton's method, falling back to
+    // bisection when a step leaves the bracket (near pericentre dt/ds is almost zero).
+    let bound = p.abs() + 2.0*q.abs();
+    let mut low = ((delta - bound)/mean).min((delta + bound)/mean);
+    let mut high = ((delta - bound)/mean).max((delta + bound)/mean);
+    let mut s = delta/mean;
+    for _ in 0..100 {
+        let (t, dt_ds) = time(s);
+        if t > delta {
+            high = s;
+        } else {
+            low = s;
+        }
+        let mut next = s - (t - delta)/dt_ds;
+        if !(next > low && next < high) {
+            next = 0.5*(low + high);
+        }
+        let step = next - s;
+        s = next;
+        if step.abs() <= Real::EPSILON*s.abs().max(Real::MIN_POSITIVE) {
+            break;
+        }
+    }
+
+    let (sin, cos) = (omega*s).sin_cos();
+    let u = a*cos + b*sin;
+    let du = (b*cos - a*sin)*omega;
+    from_ks(u, du)
+}
+
+// KS matrix L(u). Its first three rows times u give the position; the fourth is zero.
+fn matrix(u: &Vector4) -> na::Matrix4<Real> {
+    na::Matrix4::new(
+        u[0], -u[1], -u[2],  u[3],
+        u[1],  u[0], -u[3], -u[2],
+        u[2],  u[3],  u[0],  u[1],
+        u[3], -u[2],  u[1], -u[0],
+    )
+}
+
+// u and du/ds for a relative position and velocity. u is only defined up to a rotation; the
+// branch is picked to keep the square root away from zero.
+fn to_ks(position: Vector, velocity: Vector) -> (Vector4, Vector4) {
+    let r = position.magnitude();
+    let u = if position.x >= 0.0 {
+        let u1 = (0.5*(r + position.x)).sqrt();
+        Vector4::new(u1, 0.5*position.y/u1, 0.5*position.z/u1, 0.0)
+    } else {
+        let u2 = (0.5*(r - position.x)).sqrt();
+        Vector4::new(0.5*position.y/u2, u2, 0.0, 0.5*position.z/u2)
+    };
+    let du = 0.5*matrix(&u).transpose()*Vector4::new(velocity.x, velocity.y, velocity.z, 0.0);
+    (u, du)
+}
+
+fn from_ks(u: Vector4, du: Vector4) -> (Vector, Vector) {
+    let l = matrix(&u);
+    let position = l*u;
+    let velocity = 2.0/u.magnitude_squared()*(l*du);
+    (position.xyz(), velocity.xyz())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+
+    #[test]
+    fn matches_kepler() {
+        let mu = 2.0;
+        for eccentricity in [0.0, 0.5, 0.99] {
+            let elements = Elements {
+                semi_major_axis: 1.5,
+                eccentricity,
+                inclination: 0.4,
+                longitude_of_ascending_node: 1.0,
+                argument_of_periapsis: 2.0,
+                true_anomaly: -0.3,
+            };
+            let (position, velocity) = elements.to_state(mu);
+            // Through pericentre, and over many orbits
+            for delta in [0.1, 1.0, 60.0] {
+                let (x, v) = propagate(position, velocity, mu, delta);
+                let (expected_x, expected_v) = kepler::propagate(position, velocity, mu, delta);
+                let tolerance = 1e5*Real::EPSILON*(1.0 + delta);
+                assert!((x - expected_x).magnitude() < tolerance, "e = {} after {}: {:?} instead of {:?}", eccentricity, delta, x, expected_x);
+                assert!((v - expected_v).magnitude() < tolerance*expected_v.magnitude(), "e = {} after {}: {:?} instead of {:?}", eccentricity, delta, v, expected_v);
+            }
+        }
+    }
+
+    #[test]
+    fn unbound_orbits_fall_back_on_kepler() {
+        let (position, velocity) = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 2.0, 0.0));
+        assert_eq!(propagate(position, velocity, 2.0, 3.0), kepler::propagate(position, velocity, 2.0, 3.0));
+    }
+}
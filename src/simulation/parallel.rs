@@ -0,0 +1,87 @@
+// Multithreading for force evaluation. With the `parallel` cargo feature, work is spread over a
+// rayon thread pool; without it everything runs on the calling thread.
+//
+// Every result is computed by exactly one thread, summing in the same order as the serial code,
+// and results are collected back in index order. Output is therefore bit-for-bit identical for
+// any thread count.
+
+// Where force evaluation runs. By default that's rayon's global pool, shared by every world; a
+// dedicated pool is only built when a thread count is asked for.
+#[derive(Default)]
+pub struct ThreadPool {
+    #[cfg(feature = "parallel")]
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl ThreadPool {
+    // `threads` of 0 shares the global pool, which has one thread per core
+    pub fn new(threads: usize) -> Self {
+        #[cfg(not(feature = "parallel"))]
+        let _ = threads;
+        Self {
+            #[cfg(feature = "parallel")]
+            pool: (threads > 0).then(|| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("failed to create thread pool")
+            }),
+        }
+    }
+
+    // Run `f` with `map` calls inside it using this pool
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        #[cfg(feature = "parallel")]
+        if let Some(pool) = &self.pool {
+            return pool.install(f);
+        }
+
+        f()
+    }
+}
+
+// `(0..count).map(f).collect()`, spread over the current pool's threads
+pub fn map<T: Send>(count: usize, f: impl Fn(usize) -> T + Sync + Send) -> Vec<T> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        (0..count).into_par_iter().map(f).collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    {
+        (0..count).map(f).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::gravity::{barnes_hut, fmm, DirectSum, Gravity, GravitySolver};
+    use crate::simulation::tests::cluster;
+
+    #[test]
+    fn map_keeps_index_order() {
+        let squares = ThreadPool::new(4).install(|| map(1000, |i| i*i));
+        assert!(squares.iter().enumerate().all(|(i, square)| *square == i*i));
+    }
+
+    #[test]
+    fn same_forces_for_any_thread_count() {
+        let bodies = cluster(500, 6);
+        let gravity = Gravity::new();
+        let solvers: [fn() -> Box<dyn GravitySolver>; 3] = [
+            || Box::new(DirectSum),
+            || Box::new(barnes_hut::BarnesHut::new(0.5)),
+            || Box::new(fmm::FastMultipole::new(4, 0.5)),
+        ];
+
+        for solver in solvers {
+            let solve = |threads| ThreadPool::new(threads).install(|| solver().accelerations(&bodies, &gravity));
+            let serial = solve(1);
+            for threads in [2, 3, 8] {
+                assert!(solve(threads) == serial, "{} differs with {} threads", solver().name(), threads);
+            }
+        }
+    }
+}
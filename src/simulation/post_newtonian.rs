@@ -0,0 +1,176 @@
+use crate::simulation::{gravity, parallel, Bodies, Real, Vector};
+
+// Which post-Newtonian corrections `World::nbody` adds to the Newtonian accelerations. They need
+// `Gravity::speed_of_light`, and are always unsoftened and summed directly over every pair, so
+// they cost O(N^2) whatever the solver. Meant for a few compact or relativistic bodies (binaries,
+// the inner solar system), not for whole clusters or periodic boxes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostNewtonian {
+    // Einstein-Infeld-Hoffmann 1PN terms in harmonic coordinates (as in the JPL ephemerides).
+    // Gives perihelion precession of 6*pi*G*M/(c^2*a*(1 - e^2)) per orbit.
+    pub first_order: bool,
+    // 2.5PN radiation reaction of every pair taken as an isolated binary (Lincoln & Will 1990).
+    // Drains orbital energy at the rate of the Peters quadrupole formula.
+    pub radiation_reaction: bool,
+}
+
+impl PostNewtonian {
+    pub fn new() -> Self {
+        Self {
+            first_order: true,
+            radiation_reaction: false,
+        }
+    }
+}
+
+impl Default for PostNewtonian {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Corrections to the accelerations of the bodies in `targets`, in `targets` order.
+// `accelerations` are the Newtonian ones of `targets`; other bodies' come from `bodies`. The 1PN
+// terms only need them to Newtonian order.
+pub fn accelerations(
+    terms: PostNewtonian,
+    targets: &[usize],
+    accelerations: &[Vector],
+    bodies: &Bodies,
+    gravity: &gravity::Gravity,
+) -> Vec<Vector> {
+    let c = gravity.speed_of_light.expect("post-Newtonian gravity needs the speed of light");
+    let c2 = c*c;
+    let g = gravity.g;
+    let n = bodies.len();
+    let sources: Vec<usize> = (0..n).filter(|i| bodies.mass[*i] != 0.0).collect();
+
+    let mut all: Vec<Vector> = (0..n).map(|i| bodies.acceleration(i)).collect();
+    for (i, acceleration) in targets.iter().zip(accelerations) {
+        all[*i] = *acceleration;
+    }
+    // Newtonian potential sum of G*m/r at every body, from every other body
+    let potentials = if terms.first_order {
+        parallel::map(n, |i| {
+            let position = bodies.position(i);
+            sources.iter()
+                .filter(|j| **j != i)
+                .map(|j| g*bodies.mass[*j]/(bodies.position(*j) - position).magnitude())
+                .sum::<Real>()
+        })
+    } else {
+        vec![]
+    };
+
+    parallel::map(targets.len(), |k| {
+        let a = targets[k];
+        let (xa, va) = (bodies.position(a), bodies.velocity(a));
+        let mut correction = Vector::zeros();
+
+        for b in sources.iter().copied().filter(|b| *b != a) {
+            let (xb, vb) = (bodies.position(b), bodies.velocity(b));
+            // From a to b
+            let dx = xb - xa;
+            let r = dx.magnitude();
+            let gm = g*bodies.mass[b];
+
+            if terms.first_order {
+                let radial = dx.dot(&vb)/r;
+                let factor = -4.0*potentials[a] - potentials[b]
+                    + va.magnitude_squared() + 2.0*vb.magnitude_squared() - 4.0*va.dot(&vb)
+                    - 1.5*radial*radial + 0.5*dx.dot(&all[b]);
+                correction += gm/(c2*r*r*r)*(dx*factor - dx.dot(&(4.0*va - 3.0*vb))*(va - vb))
+                    + 3.5*gm/(c2*r)*all[b];
+            }
+
+            if terms.radiation_reaction && bodies.mass[a] != 0.0 {
+                // Relative acceleration of the pair, shared out so that it moves a by m_b/M of it
+                let (x, v) = (-dx, va - vb);
+                let total = g*(bodies.mass[a] + bodies.mass[b]);
+                let eta = bodies.mass[a]*bodies.mass[b]/(bodies.mass[a] + bodies.mass[b]).powi(2);
+                let (v2, radial) = (v.magnitude_squared(), x.dot(&v)/r);
+                let relative = 8.0/5.0*eta*total*total/(c2*c2*c*r*r*r)
+                    *((3.0*v2 + 17.0/3.0*total/r)*radial*x/r - (v2 + 3.0*total/r)*v);
+                correction += gm/total*relative;
+            }
+        }
+        correction
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+    use crate::simulation::integrator::ias15::Ias15;
+    use crate::simulation::integrator::Integrator;
+    use crate::simulation::tests::{period, relative, two_body};
+    use crate::simulation::World;
+
+    const PI: Real = std::f64::consts::PI as Real;
+
+    // `two_body` with the speed of light `c`
+    fn relativistic(mass: Real, eccentricity: Real, terms: PostNewtonian, c: Real) -> World {
+        let mut world = two_body(mass, eccentricity);
+        world.post_newtonian = Some(terms);
+        world.gravity.speed_of_light = Some(c);
+        world
+    }
+
+    fn elements(world: &World) -> Elements {
+        let (position, velocity) = relative(world);
+        let mu = world.gravity.g*(world.bodies.mass[0] + world.bodies.mass[1]);
+        Elements::from_state(position, velocity, mu)
+    }
+
+    #[test]
+    fn perihelion_precession() {
+        let (c, eccentricity) = (30.0, 0.5);
+        let terms = PostNewtonian { first_order: true, radiation_reaction: false };
+        let mut world = relativistic(1e-6, eccentricity, terms, c);
+
+        // The osculating elements wobble around the orbit by about G*M/(c^2*a), so they are only
+        // compared at pericentre, which `two_body` starts from. Passages are the closest samples.
+        let initial = elements(&world);
+        let mut integrator = Ias15::new();
+        let delta = period(1e-6)/500.0;
+        let (mut passages, mut last, mut approaching) = (0, initial, false);
+        let mut previous = relative(&world).0.magnitude();
+        while passages < 10 {
+            let before = elements(&world);
+            integrator.step(&mut world, delta);
+            let r = relative(&world).0.magnitude();
+            if approaching && r > previous {
+                passages += 1;
+                last = before;
+            }
+            (approaching, previous) = (r < previous, r);
+        }
+
+        // 6*pi*G*M/(c^2*a*(1 - e^2)) per orbit
+        let expected = 10.0*6.0*PI/(c*c*(1.0 - eccentricity*eccentricity));
+        let precession = last.argument_of_periapsis - initial.argument_of_periapsis;
+        assert!((precession - expected).abs() < 0.03*expected, "{} instead of {}", precession, expected);
+    }
+
+    #[test]
+    fn radiation_reaction_shrinks_orbit() {
+        // Equal masses on a circular orbit: da/dt = -64/5*G^3*m1*m2*M/(c^5*a^3)
+        let (c, orbits) = (10.0, 10);
+        let terms = PostNewtonian { first_order: false, radiation_reaction: true };
+        let mut world = relativistic(1.0, 0.0, terms, c);
+        let initial = elements(&world);
+        let mut integrator = Ias15::new();
+        for _ in 0..20*orbits {
+            integrator.step(&mut world, period(1.0)/20.0);
+        }
+        let last = elements(&world);
+
+        let (m1, m2, total) = (1.0, 1.0, 2.0);
+        let expected = -64.0/5.0*m1*m2*total/c.powi(5)*orbits as Real*period(1.0);
+        let shrinkage = last.semi_major_axis - initial.semi_major_axis;
+        assert!((shrinkage - expected).abs() < 0.05*expected.abs(), "{} instead of {}", shrinkage, expected);
+        // Circular orbits stay circular
+        assert!(last.eccentricity < 1e-3, "eccentricity {}", last.eccentricity);
+    }
+}
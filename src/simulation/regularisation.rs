@@ -0,0 +1,270 @@
+use crate::simulation::{ks, Real, Vector, World};
+
+// Kustaanheimo-Stiefel regularisation of hard binaries. A tight, bound pair otherwise forces an
+// adaptive integrator down to a tiny fraction of its orbit, and ruins a fixed step one.
+//
+// While a pair is regularised the integrator only sees its centre of mass: `World::nbody` gives
+// both members their mass-weighted mean acceleration, so their mutual pull cancels. The relative
+// orbit is advanced separately over each step by `ks::propagate`, with the tidal pull of every
+// other body applied as a kick at either end. Pairs are picked up and released automatically at
+// the start of each step of a `Simulation`.
+//
+// The centre of mass follows from any integrator which takes its forces from `World::nbody`.
+// The relative orbit is always unsoftened, so `radius` should be well above the softening length.
+pub struct Regularisation {
+    // Bound pairs closer than this whose orbit fits inside `radius*exit_factor` are regularised
+    pub radius: Real,
+    // Pairs are released once unbound or on an orbit reaching beyond `radius*exit_factor`
+    pub exit_factor: Real,
+    // Most tidal perturbation |P|*r^2/(G*M) from other bodies for a pair to be regularised,
+    // with P the difference in their pull on the two members
+    pub max_perturbation: Real,
+
+    // Names of the members of each regularised pair
+    pairs: Vec<[String; 2]>,
+    // Relative positions and velocities of the pairs by the end of the current step
+    predicted: Vec<(Vector, Vector)>,
+}
+
+impl Regularisation {
+    pub fn new(radius: Real) -> Self {
+        Self {
+            radius,
+            exit_factor: 2.0,
+            max_perturbation: 0.01,
+            pairs: vec![],
+            predicted: vec![],
+        }
+    }
+
+    pub fn pairs(&self) -> &[[String; 2]] {
+        &self.pairs
+    }
+
+    // Indices of the members of every pair. Pairs with a member which no longer exists (e.g.
+    // merged in a collision) are skipped.
+    pub fn members(&self, world: &World) -> Vec<(usize, usize)> {
+        self.pairs.iter()
+            .filter_map(|[a, b]| Some((world.bodies.index_of(a)?, world.bodies.index_of(b)?)))
+            .collect()
+    }
+}
+
+// Relative position and velocity of `j` about `i`, with mu = G*(m_i + m_j)
+fn relative(world: &World, i: usize, j: usize) -> (Vector, Vector, Real) {
+    let b = &world.bodies;
+    (
+        world.separation(&b.position(i), &b.position(j)),
+        b.velocity(j) - b.velocity(i),
+        world.gravity.g*(b.mass[i] + b.mass[j]),
+    )
+}
+
+// Difference between the pull of everything else on `j` and on `i`, summed directly
+fn perturbation(world: &World, i: usize, j: usize) -> Vector {
+    let (b, gravity) = (&world.bodies, &world.gravity);
+    let pull = |target: usize| {
+        let position = b.position(target);
+        let mut acceleration = Vector::zeros();
+        for k in (0..b.len()).filter(|k| *k != i && *k != j && b.mass[*k] != 0.0) {
+            let epsilon = gravity.pair_softening(b.softening[target], b.softening[k]);
+            acceleration += gravity.acceleration(b.position(k) - position, b.mass[k], epsilon);
+        }
+        acceleration
+    };
+    pull(j) - pull(i)
+}
+
+// Whether `i` and `j` should be regularised, given whether they already are
+fn qualifies(world: &World, regularisation: &Regularisation, i: usize, j: usize, already: bool) -> bool {
+    let (position, velocity, mu) = relative(world, i, j);
+    let r = position.magnitude();
+    let energy = 0.5*velocity.magnitude_squared() - mu/r;
+    let exit = regularisation.radius*regularisation.exit_factor;
+    if mu == 0.0 || energy >= 0.0 || (!already && r > regularisation.radius) {
+        return false;
+    }
+    // Apocentre inside the exit radius, so an unperturbed pair is never released. Perturbations
+    // can widen the orbit of a pair already regularised, so it is checked for those too.
+    let a = -mu/(2.0*energy);
+    let e = (1.0 - position.cross(&velocity).magnitude_squared()/(mu*a)).max(0.0).sqrt();
+    if a*(1.0 + e) > exit {
+        return false;
+    }
+    perturbation(world, i, j).magnitude()*r*r/mu <= regularisation.max_perturbation
+}
+
+// Release pairs which no longer qualify and pick up new ones, then kick every pair's relative
+// orbit for half of `delta` and predict it to the end of the step. Call before the integrator.
+pub fn begin_step(world: &mut World, delta: Real) {
+    let Some(mut regularisation) = world.regularisation.take() else {
+        return;
+    };
+
+    let mut pairs: Vec<(usize, usize)> = regularisation.members(world).into_iter()
+        .filter(|(i, j)| qualifies(world, &regularisation, *i, *j, true))
+        .collect();
+
+    // Candidates from a sweep along x, only among dynamic bodies with mass not already paired
+    let b = &world.bodies;
+    let mut paired = vec![false; b.len()];
+    pairs.iter().for_each(|(i, j)| { paired[*i] = true; paired[*j] = true; });
+    let mut order: Vec<usize> = (0..b.len())
+        .filter(|i| b.kind[*i].is_dynamic() && b.mass[*i] != 0.0 && !paired[*i])
+        .collect();
+    order.sort_by(|i, j| b.x[*i].total_cmp(&b.x[*j]));
+    for (k, i) in order.iter().enumerate() {
+        for j in &order[k + 1..] {
+            if b.x[*j] - b.x[*i] > regularisation.radius {
+                break;
+            }
+            if !paired[*i] && !paired[*j] && qualifies(world, &regularisation, *i, *j, false) {
+                paired[*i] = true;
+                paired[*j] = true;
+                pairs.push((*i, *j));
+            }
+        }
+    }
+
+    // The half kick can unbind a pair which only just qualified. Those are released, so the
+    // integrator takes them over for this step.
+    let (mut names, mut predicted) = (vec![], vec![]);
+    for (i, j) in pairs {
+        let (position, velocity, mu) = relative(world, i, j);
+        let velocity = velocity + 0.5*delta*perturbation(world, i, j);
+        if 0.5*velocity.magnitude_squared() - mu/position.magnitude() >= 0.0 {
+            continue;
+        }
+        names.push([b.names[i].clone(), b.names[j].clone()]);
+        predicted.push(ks::propagate(position, velocity, mu, delta));
+    }
+    regularisation.pairs = names;
+    regularisation.predicted = predicted;
+
+    world.regularisation = Some(regularisation);
+}
+
+// Put the members of every pair back about their centre of mass, as moved by the integrator, on
+// the predicted relative orbit, and apply the second half kick. Call after the integrator.
+pub fn end_step(world: &mut World, delta: Real) {
+    let Some(regularisation) = world.regularisation.take() else {
+        return;
+    };
+
+    for ([a, b], (position, velocity)) in regularisation.pairs.iter().zip(&regularisation.predicted) {
+        let (Some(i), Some(j)) = (world.bodies.index_of(a), world.bodies.index_of(b)) else {
+            continue;
+        };
+        let (mi, mj) = (world.bodies.mass[i], world.bodies.mass[j]);
+        let total = mi + mj;
+        let separation = world.separation(&world.bodies.position(i), &world.bodies.position(j));
+        let centre = world.bodies.position(i) + mj/total*separation;
+        let centre_velocity = (mi*world.bodies.velocity(i) + mj*world.bodies.velocity(j))/total;
+
+        world.bodies.set_position(i, centre - mj/total*position);
+        world.bodies.set_position(j, centre + mi/total*position);
+        let velocity = velocity + 0.5*delta*perturbation(world, i, j);
+        world.bodies.set_velocity(i, centre_velocity - mj/total*velocity);
+        world.bodies.set_velocity(j, centre_velocity + mi/total*velocity);
+    }
+
+    world.regularisation = Some(regularisation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::elements::Elements;
+    use crate::simulation::{Body, MasslessFrame};
+
+    // Equal mass binary with semi-major axis 0.01 at the origin, starting at apocentre, and a
+    // unit mass a long way off
+    fn binary(eccentricity: Real) -> World {
+        let mut world = World::new();
+        let mut primary = Body::new("primary");
+        primary.mass = 0.5;
+        world.add_body(primary);
+        let mut secondary = Body::new("secondary");
+        secondary.mass = 0.5;
+        let elements = Elements {
+            semi_major_axis: 0.01,
+            eccentricity,
+            inclination: 0.2,
+            longitude_of_ascending_node: 0.0,
+            argument_of_periapsis: 0.0,
+            true_anomaly: std::f64::consts::PI as Real,
+        };
+        world.add_body_from_elements(secondary, "primary", &elements);
+        let mut perturber = Body::new("perturber");
+        perturber.position = Vector::new(10.0, 0.0, 0.0);
+        world.add_body(perturber);
+        world.shift_to_barycentre(MasslessFrame::Shift);
+        world
+    }
+
+    // Single precision can't resolve a pericentre of 0.001 five units from the origin
+    #[test]
+    #[cfg(not(feature = "single-precision"))]
+    fn hard_binary_over_long_steps() {
+        use crate::simulation::integrator::Leapfrog;
+        use crate::simulation::tests::energy;
+        use crate::simulation::Simulation;
+
+        // Each step is about eight orbits of the binary
+        let mut sim = Simulation::new();
+        sim.set_integrator(Box::new(Leapfrog::new()));
+        sim.world = binary(0.9);
+        sim.world.regularisation = Some(Regularisation::new(0.05));
+        let initial = energy(&sim.world);
+        for _ in 0..100 {
+            sim.step(0.05);
+        }
+
+        let regularisation = sim.world.regularisation.as_ref().unwrap();
+        assert_eq!(regularisation.pairs().len(), 1);
+        let error = ((energy(&sim.world) - initial)/initial).abs() as Real;
+        assert!(error < 1e-6, "energy error {:e}", error);
+    }
+
+    #[test]
+    fn released_once_orbit_reaches_exit() {
+        let mut world = binary(0.5);
+        world.regularisation = Some(Regularisation::new(0.05));
+        begin_step(&mut world, 0.01);
+        assert_eq!(world.regularisation.as_ref().unwrap().pairs().len(), 1);
+
+        // Still inside the exit radius and bound, but with an apocentre beyond it. Speeding up at
+        // apocentre turns it into the pericentre.
+        let speed_up = 1.9;
+        world.bodies.set_velocity(0, speed_up*world.bodies.velocity(0));
+        world.bodies.set_velocity(1, speed_up*world.bodies.velocity(1));
+        begin_step(&mut world, 0.01);
+        assert!(world.regularisation.as_ref().unwrap().pairs().is_empty());
+    }
+
+    #[test]
+    fn released_when_kick_unbinds() {
+        // Only just bound, moving away from each other along the pull of the perturber, which
+        // the half kick adds enough energy to unbind
+        let mut world = World::new();
+        let mut primary = Body::new("primary");
+        primary.mass = 0.5;
+        world.add_body(primary);
+        let mut secondary = Body::new("secondary");
+        secondary.mass = 0.5;
+        secondary.position = Vector::new(0.01, 0.0, 0.0);
+        let escape: Real = (2.0/0.01 as Real).sqrt();
+        secondary.velocity = (1.0 - 1e-4)*escape*Vector::new(0.6, 0.8, 0.0);
+        world.add_body(secondary);
+        let mut perturber = Body::new("perturber");
+        perturber.position = Vector::new(-1.0, 0.0, 0.0);
+        world.add_body(perturber);
+
+        let mut regularisation = Regularisation::new(0.05);
+        regularisation.exit_factor = 1e9;
+        world.regularisation = Some(regularisation);
+        begin_step(&mut world, 1.0);
+        assert!(world.regularisation.as_ref().unwrap().pairs().is_empty());
+        end_step(&mut world, 1.0);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::simulation::Real;
+
+// Force kernels used to soften gravity at short range. Softening stops close encounters from
+// producing huge velocity spikes, at the cost of no longer being exactly Newtonian inside the
+// softening length.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    // Plain 1/r^2. Only exactly coincident bodies are skipped.
+    Newtonian,
+    // Plummer sphere: 1/(r^2 + eps^2). Never exactly Newtonian, but smooth everywhere.
+    Plummer,
+    // Monaghan & Lattanzio (1985) cubic spline, as in GADGET. The kernel has compact support of
+    // h = 2.8*eps, beyond which the force is exactly Newtonian. At r = 0 the potential matches a
+    // Plummer sphere with the same eps.
+    CubicSpline,
+}
+
+impl Kernel {
+    // Returns f such that the acceleration towards a source of mass M at offset dx is
+    // G*M*f*dx. For the Newtonian kernel f = 1/r^3.
+    pub fn force_factor(&self, r2: Real, epsilon: Real) -> Real {
+        match self {
+            Kernel::Newtonian => {
+                if r2 == 0.0 {
+                    0.0
+                } else {
+                    1.0/(r2*r2.sqrt())
+                }
+            }
+            Kernel::Plummer => {
+                let s2 = r2 + epsilon*epsilon;
+                if s2 == 0.0 {
+                    0.0
+                } else {
+                    1.0/(s2*s2.sqrt())
+                }
+            }
+            Kernel::CubicSpline => {
+                let h = 2.8*epsilon;
+                let r = r2.sqrt();
+                if r >= h {
+                    return Kernel::Newtonian.force_factor(r2, epsilon);
+                }
+
+                // Coefficients from GADGET-2 (Springel 2005)
+                let u = r/h;
+                let h3 = h*h*h;
+                if u < 0.5 {
+                    (32.0/3.0 + u*u*(32.0*u - 38.4))/h3
+                } else {
+                    (64.0/3.0 - 48.0*u + 38.4*u*u - 32.0/3.0*u*u*u - 1.0/(15.0*u*u*u))/h3
+                }
+            }
+        }
+    }
+
+    // Returns g = (df/dr)/r for the f of `force_factor`, so that the jerk due to a source at
+    // relative velocity dv is G*M*(f*dv + g*(dx.dv)*dx). For the Newtonian kernel g = -3/r^5.
+    pub fn jerk_factor(&self, r2: Real, epsilon: Real) -> Real {
+        match self {
+            Kernel::Newtonian => {
+                if r2 == 0.0 {
+                    0.0
+                } else {
+                    -3.0/(r2*r2*r2.sqrt())
+                }
+            }
+            Kernel::Plummer => {
+                let s2 = r2 + epsilon*epsilon;
+                if s2 == 0.0 {
+                    0.0
+                } else {
+                    -3.0/(s2*s2*s2.sqrt())
+                }
+            }
+            Kernel::CubicSpline => {
+                let h = 2.8*epsilon;
+                let r = r2.sqrt();
+                if r >= h {
+                    return Kernel::Newtonian.jerk_factor(r2, epsilon);
+                }
+
+                // Derivatives of the polynomials above, divided by u
+                let u = r/h;
+                let h5 = h*h*h*h*h;
+                if u < 0.5 {
+                    (96.0*u - 76.8)/h5
+                } else {
+                    (-48.0 + 76.8*u - 32.0*u*u + 1.0/(5.0*u*u*u*u))/(u*h5)
+                }
+            }
+        }
+    }
+
+    // Returns phi such that the potential energy of two bodies at separation squared `r2` is
+    // -G*m1*m2*phi, consistent with `force_factor`. For the Newtonian kernel phi = 1/r.
+    pub fn potential_factor(&self, r2: Real, epsilon: Real) -> Real {
+        match self {
+            Kernel::Newtonian => {
+                if r2 == 0.0 {
+                    0.0
+                } else {
+                    1.0/r2.sqrt()
+                }
+            }
+            Kernel::Plummer => {
+                let s2 = r2 + epsilon*epsilon;
+                if s2 == 0.0 {
+                    0.0
+                } else {
+                    1.0/s2.sqrt()
+                }
+            }
+            Kernel::CubicSpline => {
+                let h = 2.8*epsilon;
+                let r = r2.sqrt();
+                if r >= h {
+                    return Kernel::Newtonian.potential_factor(r2, epsilon);
+                }
+
+                // Integrals of the polynomials above, again from GADGET-2
+                let u = r/h;
+                let u2 = u*u;
+                if u < 0.5 {
+                    (14.0/5.0 - u2*(16.0/3.0 + u2*(32.0/5.0*u - 48.0/5.0)))/h
+                } else {
+                    (16.0/5.0 - 1.0/(15.0*u) - u2*(32.0/3.0 + u*(-16.0 + u*(48.0/5.0 - 32.0/15.0*u))))/h
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newtonian_outside_softening() {
+        let epsilon = 0.1;
+        for r in [0.3, 1.0, 10.0] {
+            let newtonian = Kernel::Newtonian.force_factor(r*r, epsilon);
+            assert_eq!(Kernel::CubicSpline.force_factor(r*r, epsilon), newtonian);
+            // Plummer only approaches it
+            let plummer = Kernel::Plummer.force_factor(r*r, epsilon);
+            assert!(plummer < newtonian && plummer > newtonian*(1.0 - 2.0*epsilon*epsilon/(r*r)));
+        }
+        assert!(Kernel::Plummer.force_factor(0.0, epsilon).is_finite());
+        assert!(Kernel::CubicSpline.force_factor(0.0, epsilon).is_finite());
+    }
+
+    #[test]
+    fn spline_is_continuous() {
+        let (epsilon, h) = (0.1, 0.28);
+        for r in [0.5*h, h] {
+            let below = Kernel::CubicSpline.force_factor((r - 1e-9)*(r - 1e-9), epsilon);
+            let above = Kernel::CubicSpline.force_factor((r + 1e-9)*(r + 1e-9), epsilon);
+            assert!((below - above).abs() < 1e-5*above, "r = {}: {} {}", r, below, above);
+        }
+    }
+
+    // Finite differences need double precision
+    #[test]
+    #[cfg(not(feature = "single-precision"))]
+    fn jerk_factor_is_derivative() {
+        let (epsilon, step) = (0.1, 1e-6);
+        for kernel in [Kernel::Newtonian, Kernel::Plummer, Kernel::CubicSpline] {
+            for r in [0.05, 0.1, 0.2, 0.5, 2.0] {
+                let f = |r: Real| kernel.force_factor(r*r, epsilon);
+                let derivative = (f(r + step) - f(r - step))/(2.0*step);
+                let g = kernel.jerk_factor(r*r, epsilon);
+                assert!((g*r - derivative).abs() < 1e-5*derivative.abs(), "{:?} r = {}", kernel, r);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// Unit systems the simulation can work in. The choice only decides the values of G and c (and how
+// to convert results back to SI); the integrators themselves are unit agnostic.
+
+// IAU 2012 astronomical unit, metres
+const ASTRONOMICAL_UNIT: f64 = 1.495_978_707e11;
+// Nominal solar mass parameter GM (IAU 2015), m^3 s^-2
+const SOLAR_MASS_PARAMETER: f64 = 1.327_124_4e20;
+// CODATA 2018, m^3 kg^-1 s^-2
+const GRAVITATIONAL_CONSTANT_SI: f64 = 6.674_30e-11;
+// Gaussian gravitational constant, AU^(3/2) M_sun^(-1/2) day^-1
+const GAUSSIAN_GRAVITATIONAL_CONSTANT: f64 = 0.017_202_098_95;
+// Exact by definition of the metre, m s^-1
+const SPEED_OF_LIGHT_SI: f64 = 299_792_458.0;
+const JULIAN_YEAR_DAYS: f64 = 365.25;
+const DAY: f64 = 86_400.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnitSystem {
+    // Metres, kilograms, seconds
+    SI,
+    // Astronomical units, solar masses and Julian years. G follows from the Gaussian constant
+    // (about 4*pi^2), which is what most published ephemerides use.
+    Astronomical,
+    // Henon N-body units with G = 1. Length, mass and time scales are set by the initial
+    // conditions rather than fixed.
+    NBody,
+}
+
+impl UnitSystem {
+    pub fn gravitational_constant(&self) -> f64 {
+        match self {
+            UnitSystem::SI => GRAVITATIONAL_CONSTANT_SI,
+            UnitSystem::Astronomical => (GAUSSIAN_GRAVITATIONAL_CONSTANT*JULIAN_YEAR_DAYS).powi(2),
+            UnitSystem::NBody => 1.0,
+        }
+    }
+
+    // Speed of light, for post-Newtonian gravity. None for N-body units, where it depends on what
+    // the length and time units stand for and has to be chosen along with them.
+    pub fn speed_of_light(&self) -> Option<f64> {
+        Some(SPEED_OF_LIGHT_SI*self.time_unit()?/self.length_unit()?)
+    }
+
+    // Size of one length unit in metres. None for N-body units, which have no fixed scale.
+    pub fn length_unit(&self) -> Option<f64> {
+        match self {
+            UnitSystem::SI => Some(1.0),
+            UnitSystem::Astronomical => Some(ASTRONOMICAL_UNIT),
+            UnitSystem::NBody => None,
+        }
+    }
+
+    // Size of one mass unit in kilograms
+    pub fn mass_unit(&self) -> Option<f64> {
+        match self {
+            UnitSystem::SI => Some(1.0),
+            UnitSystem::Astronomical => Some(SOLAR_MASS_PARAMETER/GRAVITATIONAL_CONSTANT_SI),
+            UnitSystem::NBody => None,
+        }
+    }
+
+    // Size of one time unit in seconds
+    pub fn time_unit(&self) -> Option<f64> {
+        match self {
+            UnitSystem::SI => Some(1.0),
+            UnitSystem::Astronomical => Some(JULIAN_YEAR_DAYS*DAY),
+            UnitSystem::NBody => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gravitational_constants() {
+        assert_eq!(UnitSystem::SI.gravitational_constant(), 6.674_30e-11);
+        assert_eq!(UnitSystem::NBody.gravitational_constant(), 1.0);
+        // Close to 4*pi^2 in AU, solar masses and years
+        let g = UnitSystem::Astronomical.gravitational_constant();
+        assert!((g - 4.0*std::f64::consts::PI.powi(2)).abs() < 2e-3*g);
+        // Which agrees with the SI value converted
+        let si = g*UnitSystem::Astronomical.length_unit().unwrap().powi(3)
+            /UnitSystem::Astronomical.mass_unit().unwrap()
+            /UnitSystem::Astronomical.time_unit().unwrap().powi(2);
+        assert!((si - GRAVITATIONAL_CONSTANT_SI).abs() < 1e-6*GRAVITATIONAL_CONSTANT_SI);
+    }
+
+    #[test]
+    fn speed_of_light() {
+        assert_eq!(UnitSystem::SI.speed_of_light(), Some(299_792_458.0));
+        // About 63,241 AU per year
+        let c = UnitSystem::Astronomical.speed_of_light().unwrap();
+        assert!((c - 63_241.0).abs() < 1.0, "{}", c);
+        assert_eq!(UnitSystem::NBody.speed_of_light(), None);
+    }
+}